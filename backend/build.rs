@@ -0,0 +1,7 @@
+// Rebuild when a migration is added so sqlx::migrate! embeds it, and when the
+// offline query cache is regenerated (`cargo sqlx prepare`) so SQLX_OFFLINE
+// builds check against it
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=.sqlx");
+}
@@ -0,0 +1,9 @@
+-- Node registry: one row per node that has joined, with its current neighbours
+CREATE TABLE nodes (
+    id          BIGINT PRIMARY KEY,
+    address     TEXT NOT NULL,
+    port        INTEGER NOT NULL,
+    predecessor BIGINT REFERENCES nodes (id) ON DELETE SET NULL,
+    successor   BIGINT REFERENCES nodes (id) ON DELETE SET NULL,
+    status      TEXT NOT NULL DEFAULT 'alive' CHECK (status IN ('alive', 'failed'))
+);
@@ -0,0 +1,8 @@
+-- Key-value rows, each held by one node; the primary key doubles as the (node_id, key) index
+-- used by every lookup, scan and transfer
+CREATE TABLE key_values (
+    node_id BIGINT NOT NULL REFERENCES nodes (id) ON DELETE CASCADE,
+    key     INTEGER NOT NULL,
+    value   TEXT NOT NULL,
+    PRIMARY KEY (node_id, key)
+);
@@ -0,0 +1,3 @@
+-- Keys become arbitrary byte strings. Existing integer keys keep their decimal text, which is
+-- also what they were hashed by, so no row changes owner.
+ALTER TABLE key_values ALTER COLUMN key TYPE BYTEA USING convert_to(key::TEXT, 'UTF8');
@@ -0,0 +1,7 @@
+-- Tag each row with the node that owns the key and the row's place in its replica set:
+-- rank 0 is the owner's copy, ranks 1..N-1 sit on the owner's successors.
+-- Rows written before replication was tagged are taken to be the holder's own.
+ALTER TABLE key_values ADD COLUMN owner_id BIGINT;
+UPDATE key_values SET owner_id = node_id;
+ALTER TABLE key_values ALTER COLUMN owner_id SET NOT NULL;
+ALTER TABLE key_values ADD COLUMN replica_rank SMALLINT NOT NULL DEFAULT 0 CHECK (replica_rank >= 0);
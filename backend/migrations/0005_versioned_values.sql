@@ -0,0 +1,11 @@
+-- Each row holds the key's concurrent versions instead of a single value: a JSON array of
+-- {"value", "dot": {"node", "counter"}, "context"} objects, see nodes::version.
+-- Values written before versioning become one version with counter 0, which any later write replaces.
+ALTER TABLE key_values ADD COLUMN versions JSONB;
+UPDATE key_values SET versions = jsonb_build_array(jsonb_build_object(
+    'value', value,
+    'dot', jsonb_build_object('node', owner_id, 'counter', 0),
+    'context', '{}'::jsonb
+));
+ALTER TABLE key_values ALTER COLUMN versions SET NOT NULL;
+ALTER TABLE key_values DROP COLUMN value;
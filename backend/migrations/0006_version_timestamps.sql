@@ -0,0 +1,12 @@
+-- Stamp every stored version with a hybrid logical clock timestamp {"wall_ms", "logical", "node"}.
+-- Versions written before timestamps get the earliest one, from the node that coordinated them,
+-- so any later write wins over them in last-writer-wins namespaces.
+UPDATE key_values SET versions = COALESCE(
+    (SELECT jsonb_agg(version || jsonb_build_object('timestamp', jsonb_build_object(
+        'wall_ms', 0,
+        'logical', 0,
+        'node', version->'dot'->'node'
+    )))
+    FROM jsonb_array_elements(versions) AS version),
+    '[]'::jsonb
+);
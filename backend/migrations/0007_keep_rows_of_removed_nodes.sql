@@ -0,0 +1,5 @@
+-- A node's registry entry must not take its key-value rows with it: removing a node that still
+-- holds data (a failed leave, the last node on the ring) is refused rather than cascaded.
+ALTER TABLE key_values DROP CONSTRAINT key_values_node_id_fkey;
+ALTER TABLE key_values ADD CONSTRAINT key_values_node_id_fkey
+    FOREIGN KEY (node_id) REFERENCES nodes (id) ON DELETE RESTRICT;
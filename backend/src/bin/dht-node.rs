@@ -0,0 +1,136 @@
+use actix::prelude::*;
+use backend::config::settings::Settings;
+use backend::storage;
+use backend::nodes::{Identifier, JoinMessage, LeaveMessage, Node, NodeConfig, NodesMap, PeerClient, TransportKind};
+use backend::ws_handler::Clients;
+use clap::Parser;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+// One Chord node per process, talking to its peers over TCP
+#[derive(Parser)]
+#[command(name = "dht-node", about = "Run a single DHT node and join it to a ring")]
+struct Args {
+    /// Settings file; defaults to $DHT_CONFIG, then ./dht.toml if present
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Node id on the ring; derived from the advertised address when left out
+    #[arg(long)]
+    id: Option<u64>,
+
+    /// Address to listen for peers on
+    #[arg(long, default_value = "127.0.0.1:5081")]
+    bind: SocketAddr,
+
+    /// host:port other nodes use to reach this one; defaults to --bind
+    #[arg(long)]
+    advertise: Option<String>,
+
+    /// host:port of any node already on the ring; without it the node starts a new ring
+    #[arg(long)]
+    bootstrap: Option<String>,
+
+    /// Overrides storage.database_url and DATABASE_URL
+    #[arg(long)]
+    database_url: Option<String>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn split_host_port(value: &str) -> io::Result<(String, i32)> {
+    let (host, port) = value
+        .rsplit_once(':')
+        .ok_or_else(|| invalid(format!("expected host:port, got {}", value)))?;
+    let port: u16 = port.parse().map_err(|_| invalid(format!("invalid port in {}", value)))?;
+    Ok((host.to_string(), port as i32))
+}
+
+#[actix::main]
+async fn main() {
+    if let Err(e) = run(Args::parse()).await {
+        eprintln!("dht-node: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(args: Args) -> io::Result<()> {
+    // Command-line flags win over the settings file and the environment
+    let mut settings = Settings::read(args.config.as_deref()).map_err(|e| invalid(e.to_string()))?;
+    if let Some(database_url) = &args.database_url {
+        settings.storage.database_url = Some(database_url.clone());
+    }
+    settings.validate().map_err(|e| invalid(e.to_string()))?;
+
+    // A lone node can only reach its peers over the network
+    let config = NodeConfig { bind: Some(args.bind), transport: TransportKind::Tcp, ..settings.node_config() };
+    let space = config.space;
+
+    // Peers must be able to dial the advertised address, so a wildcard bind needs an explicit one
+    let (address, port) = match &args.advertise {
+        Some(advertise) => split_host_port(advertise)?,
+        None if args.bind.ip().is_unspecified() => {
+            return Err(invalid(format!("--advertise is required when binding to {}", args.bind)));
+        }
+        None => (args.bind.ip().to_string(), args.bind.port() as i32),
+    };
+    let id = match args.id {
+        Some(id) if id >= space.size() => {
+            return Err(invalid(format!("--id must be below {} for a {}-bit ring", space.size(), space.bits())));
+        }
+        Some(id) => Identifier::new(id),
+        None => space.node_id(&address, port),
+    };
+
+    let stores = storage::open(&settings.storage)
+        .await
+        .map_err(|e| io::Error::other(format!("failed to open storage: {}", e)))?;
+    let nodes_map: NodesMap = Arc::new(Mutex::new(HashMap::new()));
+    let clients: Clients = Arc::new(Mutex::new(HashSet::new()));
+
+    // We only know the bootstrap node's address, so ask it who it is
+    let bootstrap = match &args.bootstrap {
+        Some(bootstrap) => {
+            let (host, bootstrap_port) = split_host_port(bootstrap)?;
+            let peers = PeerClient::for_kind(TransportKind::Tcp, nodes_map.clone(), config.rpc_timeout, config.transfer_timeout);
+            let node = peers
+                .identify(&host, bootstrap_port)
+                .await
+                .map_err(|e| io::Error::other(format!("bootstrap node {} is unreachable: {}", bootstrap, e)))?;
+            Some(node)
+        }
+        None => None,
+    };
+
+    let node = Node::new(id, address.clone(), port, config, stores.registry.clone(), stores.keys, nodes_map.clone(), clients)?.start();
+    nodes_map.lock().unwrap().insert(id, node.clone());
+    node.send(JoinMessage { node_id: id, bootstrap: bootstrap.clone() })
+        .await
+        .map_err(io::Error::other)?
+        .map_err(|e| io::Error::other(format!("node {} failed to join: {}", id, e)))?;
+    match &bootstrap {
+        Some(bootstrap) => println!("Node {} at {}:{} joined the ring through node {}", id, address, port, bootstrap.id),
+        None => println!("Node {} at {}:{} started a new ring", id, address, port),
+    }
+
+    // Leave gracefully on Ctrl-C so our keys end up on our successor
+    tokio::signal::ctrl_c().await?;
+    // Only drop our registry entry once the keys are safely with our successor;
+    // otherwise the entry is what still points at the data we hold
+    match node.send(LeaveMessage).await {
+        Ok(Ok(summary)) => {
+            println!("Node {} left the ring, handed {} keys to its successor", id, summary.keys_moved);
+            if let Err(e) = stores.registry.remove(id).await {
+                println!("Failed to remove node {} from the registry: {}", id, e);
+            }
+        }
+        Ok(Err(e)) => println!("Node {} failed to leave cleanly, keeping its registry entry: {}", id, e),
+        Err(e) => println!("Node {} failed to leave cleanly, keeping its registry entry: {}", id, e),
+    }
+    Ok(())
+}
@@ -1,14 +1,12 @@
-use sqlx::{PgPool, Pool, Postgres};
-use std::env;
-use dotenv::dotenv;
+use sqlx::PgPool;
 use sqlx::postgres::PgPoolOptions;
+use super::settings::StorageSettings;
 
-pub async fn create_pool() -> PgPool {
-    dotenv().ok();
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+// Settings::validate guarantees a database URL whenever a store lives in Postgres
+pub async fn create_pool(storage: &StorageSettings) -> Result<PgPool, sqlx::Error> {
+    let database_url = storage.database_url.as_deref().unwrap_or_default();
     PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+        .max_connections(storage.max_connections)
+        .connect(database_url)
         .await
-        .expect("Failed to create database pool")
-}
\ No newline at end of file
+}
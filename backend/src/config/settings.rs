@@ -0,0 +1,617 @@
+// Cluster and node settings: a TOML file, then DHT_* environment overrides, then validation.
+// Every setting has a default, so an empty (or missing) file describes the stock three-node ring.
+
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use crate::nodes::identifier::{DEFAULT_RING_BITS, MAX_RING_BITS};
+use crate::nodes::version::ConflictPolicy;
+use crate::nodes::{Identifier, IdentifierSpace, NodeConfig, TransportKind};
+use crate::storage::MAX_REPLICATION_FACTOR;
+
+// Read when no path is given and DHT_CONFIG is unset, if it exists
+pub const DEFAULT_CONFIG_PATH: &str = "dht.toml";
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub ring: RingSettings,
+    pub timers: TimerSettings,
+    pub transport: TransportSettings,
+    pub storage: StorageSettings,
+    pub http: HttpSettings,
+    pub websocket: WebSocketSettings,
+    pub conflicts: ConflictSettings,
+    // Nodes the HTTP server starts in-process
+    pub nodes: Vec<NodeSettings>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RingSettings {
+    // m: the ring has 2^m positions
+    pub bits: u32,
+    // r: successors each node tracks
+    pub successor_list_len: usize,
+    // Copies of each key, counting the owner; replicas go to the first successors
+    pub replication_factor: usize,
+}
+
+impl Default for RingSettings {
+    fn default() -> Self {
+        RingSettings { bits: DEFAULT_RING_BITS, successor_list_len: 3, replication_factor: 3 }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimerSettings {
+    pub stabilize_interval_ms: u64,
+    pub fix_fingers_interval_ms: u64,
+    pub check_predecessor_interval_ms: u64,
+    pub rpc_timeout_ms: u64,
+    // For requests that move a batch of rows (key handoff on join and leave), which can take far longer
+    pub transfer_timeout_ms: u64,
+}
+
+impl Default for TimerSettings {
+    fn default() -> Self {
+        TimerSettings {
+            stabilize_interval_ms: 5_000,
+            fix_fingers_interval_ms: 60_000,
+            check_predecessor_interval_ms: 5_000,
+            rpc_timeout_ms: 2_000,
+            transfer_timeout_ms: 60_000,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransportSettings {
+    pub kind: TransportKind,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    // The key_values table in the database at storage.database_url
+    #[default]
+    Postgres,
+    // Process memory; everything is lost on exit
+    Memory,
+    // A redb file at storage.path
+    Embedded,
+}
+
+// Where the node registry (the nodes table) lives
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistryBackend {
+    Postgres,
+    Memory,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSettings {
+    pub backend: StorageBackend,
+    // Follows the key backend when left out: Postgres with Postgres, memory otherwise
+    pub registry: Option<RegistryBackend>,
+    pub database_url: Option<String>,
+    pub max_connections: u32,
+    // File used by the embedded backend
+    pub path: PathBuf,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            backend: StorageBackend::default(),
+            registry: None,
+            database_url: None,
+            max_connections: 5,
+            path: PathBuf::from("dht-data.redb"),
+        }
+    }
+}
+
+impl StorageSettings {
+    pub fn registry_backend(&self) -> RegistryBackend {
+        match (self.registry, self.backend) {
+            (Some(registry), _) => registry,
+            (None, StorageBackend::Postgres) => RegistryBackend::Postgres,
+            (None, _) => RegistryBackend::Memory,
+        }
+    }
+
+    // Whether anything lives in Postgres; without it the ring runs with no database server at all
+    pub fn needs_database(&self) -> bool {
+        self.backend == StorageBackend::Postgres || self.registry_backend() == RegistryBackend::Postgres
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpSettings {
+    pub bind: SocketAddr,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings { bind: SocketAddr::from(([127, 0, 0, 1], 5080)) }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebSocketSettings {
+    // How often the server pings each client
+    pub heartbeat_interval_ms: u64,
+    // Clients silent for longer than this are disconnected
+    pub client_timeout_ms: u64,
+}
+
+impl Default for WebSocketSettings {
+    fn default() -> Self {
+        WebSocketSettings { heartbeat_interval_ms: 5_000, client_timeout_ms: 10_000 }
+    }
+}
+
+impl WebSocketSettings {
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_millis(self.heartbeat_interval_ms)
+    }
+
+    pub fn client_timeout(&self) -> Duration {
+        Duration::from_millis(self.client_timeout_ms)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConflictSettings {
+    // Key namespaces (the part of a key before its first ':') where the newest write replaces the others;
+    // every other key keeps concurrent writes as siblings
+    pub last_writer_wins: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NodeSettings {
+    pub address: String,
+    pub port: i32,
+    // Derived from address:port when left out
+    pub id: Option<u64>,
+}
+
+impl NodeSettings {
+    pub fn identifier(&self, space: &IdentifierSpace) -> Identifier {
+        self.id.map_or_else(|| space.node_id(&self.address, self.port), Identifier::new)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read { path: PathBuf, source: io::Error },
+    Parse { path: PathBuf, source: toml::de::Error },
+    Env { var: &'static str, value: String, reason: String },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, source } => write!(f, "cannot read {}: {}", path.display(), source),
+            ConfigError::Parse { path, source } => write!(f, "invalid {}: {}", path.display(), source),
+            ConfigError::Env { var, value, reason } => write!(f, "invalid {}={:?}: {}", var, value, reason),
+            ConfigError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+fn invalid(reason: String) -> Result<(), ConfigError> {
+    Err(ConfigError::Invalid(reason))
+}
+
+impl Settings {
+    // Read and validate; see `read` for where settings come from
+    pub fn load(path: Option<&Path>) -> Result<Settings, ConfigError> {
+        let settings = Settings::read(path)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    // Read `path`, else $DHT_CONFIG, else dht.toml if present, else defaults; then apply the environment
+    pub fn read(path: Option<&Path>) -> Result<Settings, ConfigError> {
+        dotenv::dotenv().ok();
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os("DHT_CONFIG").map(PathBuf::from))
+            .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_PATH)).filter(|path| path.exists()));
+
+        let mut settings = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(&path).map_err(|source| ConfigError::Read { path: path.clone(), source })?;
+                toml::from_str(&text).map_err(|source| ConfigError::Parse { path, source })?
+            }
+            None => Settings::default(),
+        };
+        settings.apply_env(|var| std::env::var(var).ok())?;
+        Ok(settings)
+    }
+
+    // Environment variables win over the file
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        parsed(&var, "DHT_RING_BITS", &mut self.ring.bits)?;
+        parsed(&var, "DHT_SUCCESSOR_LIST_LEN", &mut self.ring.successor_list_len)?;
+        parsed(&var, "DHT_REPLICATION_FACTOR", &mut self.ring.replication_factor)?;
+        parsed(&var, "DHT_STABILIZE_INTERVAL_MS", &mut self.timers.stabilize_interval_ms)?;
+        parsed(&var, "DHT_FIX_FINGERS_INTERVAL_MS", &mut self.timers.fix_fingers_interval_ms)?;
+        parsed(&var, "DHT_CHECK_PREDECESSOR_INTERVAL_MS", &mut self.timers.check_predecessor_interval_ms)?;
+        parsed(&var, "DHT_RPC_TIMEOUT_MS", &mut self.timers.rpc_timeout_ms)?;
+        parsed(&var, "DHT_TRANSFER_TIMEOUT_MS", &mut self.timers.transfer_timeout_ms)?;
+        named(&var, "DHT_TRANSPORT", &mut self.transport.kind)?;
+        named(&var, "DHT_STORAGE_BACKEND", &mut self.storage.backend)?;
+        if var("DHT_REGISTRY").is_some() {
+            let mut registry = RegistryBackend::Memory;
+            named(&var, "DHT_REGISTRY", &mut registry)?;
+            self.storage.registry = Some(registry);
+        }
+        if let Some(url) = var("DATABASE_URL") {
+            self.storage.database_url = Some(url);
+        }
+        parsed(&var, "DHT_DB_MAX_CONNECTIONS", &mut self.storage.max_connections)?;
+        parsed(&var, "DHT_STORAGE_PATH", &mut self.storage.path)?;
+        parsed(&var, "DHT_HTTP_BIND", &mut self.http.bind)?;
+        parsed(&var, "DHT_WS_HEARTBEAT_INTERVAL_MS", &mut self.websocket.heartbeat_interval_ms)?;
+        parsed(&var, "DHT_WS_CLIENT_TIMEOUT_MS", &mut self.websocket.client_timeout_ms)?;
+        listed(&var, "DHT_LWW_NAMESPACES", &mut self.conflicts.last_writer_wins);
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let ring = &self.ring;
+        if !(1..=MAX_RING_BITS).contains(&ring.bits) {
+            return invalid(format!("ring.bits must be between 1 and {}, got {}", MAX_RING_BITS, ring.bits));
+        }
+        if ring.successor_list_len == 0 {
+            return invalid("ring.successor_list_len must be at least 1".to_string());
+        }
+        if ring.replication_factor == 0 || ring.replication_factor > ring.successor_list_len + 1 {
+            return invalid(format!(
+                "ring.replication_factor must be between 1 and successor_list_len + 1 ({}), got {}",
+                ring.successor_list_len + 1,
+                ring.replication_factor
+            ));
+        }
+        if ring.replication_factor > MAX_REPLICATION_FACTOR {
+            return invalid(format!(
+                "ring.replication_factor must be at most {}, got {}",
+                MAX_REPLICATION_FACTOR, ring.replication_factor
+            ));
+        }
+
+        let timers = [
+            ("timers.stabilize_interval_ms", self.timers.stabilize_interval_ms),
+            ("timers.fix_fingers_interval_ms", self.timers.fix_fingers_interval_ms),
+            ("timers.check_predecessor_interval_ms", self.timers.check_predecessor_interval_ms),
+            ("timers.rpc_timeout_ms", self.timers.rpc_timeout_ms),
+            ("timers.transfer_timeout_ms", self.timers.transfer_timeout_ms),
+            ("websocket.heartbeat_interval_ms", self.websocket.heartbeat_interval_ms),
+        ];
+        if let Some((name, _)) = timers.iter().find(|(_, ms)| *ms == 0) {
+            return invalid(format!("{} must be greater than zero", name));
+        }
+        if self.websocket.client_timeout_ms <= self.websocket.heartbeat_interval_ms {
+            return invalid("websocket.client_timeout_ms must be longer than websocket.heartbeat_interval_ms".to_string());
+        }
+
+        if self.storage.max_connections == 0 {
+            return invalid("storage.max_connections must be at least 1".to_string());
+        }
+        // key_values rows reference the nodes table, so Postgres keys need the Postgres registry
+        if self.storage.backend == StorageBackend::Postgres && self.storage.registry_backend() != RegistryBackend::Postgres {
+            return invalid("storage.registry must be postgres when storage.backend is postgres".to_string());
+        }
+        if self.storage.needs_database() && self.storage.database_url.as_deref().unwrap_or("").is_empty() {
+            return invalid("storage.database_url (or DATABASE_URL) is required when keys or the node registry live in Postgres".to_string());
+        }
+        if self.storage.backend == StorageBackend::Embedded && self.storage.path.as_os_str().is_empty() {
+            return invalid("storage.path is required for the embedded backend".to_string());
+        }
+
+        if let Some(namespace) = self.conflicts.last_writer_wins.iter().find(|ns| ns.is_empty() || ns.contains(':')) {
+            return invalid(format!("conflicts.last_writer_wins: {:?} is not a namespace; leave out the ':'", namespace));
+        }
+
+        let space = self.space();
+        let ring_size = space.size();
+        let mut seen = HashSet::new();
+        let mut ids = HashSet::new();
+        for node in &self.nodes {
+            let name = format!("{}:{}", node.address, node.port);
+            if node.address.is_empty() {
+                return invalid("nodes: address must not be empty".to_string());
+            }
+            if !(1..=65535).contains(&node.port) {
+                return invalid(format!("nodes: {} has an invalid port", name));
+            }
+            if node.port == i32::from(self.http.bind.port()) {
+                return invalid(format!("nodes: {} uses the HTTP port {}", name, self.http.bind.port()));
+            }
+            if node.id.is_some_and(|id| id >= ring_size) {
+                return invalid(format!("nodes: id of {} must be below {}", name, ring_size));
+            }
+            if !seen.insert(name.clone()) {
+                return invalid(format!("nodes: {} is listed twice", name));
+            }
+            let id = node.identifier(&space);
+            if !ids.insert(id) {
+                return invalid(format!("nodes: {} has id {}, which another node already uses", name, id));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn space(&self) -> IdentifierSpace {
+        IdentifierSpace::new(self.ring.bits)
+    }
+
+    // Per-node settings handed to every Node actor
+    pub fn node_config(&self) -> NodeConfig {
+        NodeConfig {
+            space: self.space(),
+            stabilize_interval: Duration::from_millis(self.timers.stabilize_interval_ms),
+            fix_fingers_interval: Duration::from_millis(self.timers.fix_fingers_interval_ms),
+            successor_list_len: self.ring.successor_list_len,
+            replication_factor: self.ring.replication_factor,
+            rpc_timeout: Duration::from_millis(self.timers.rpc_timeout_ms),
+            transfer_timeout: Duration::from_millis(self.timers.transfer_timeout_ms),
+            check_predecessor_interval: Duration::from_millis(self.timers.check_predecessor_interval_ms),
+            transport: self.transport.kind,
+            bind: None,
+            conflicts: ConflictPolicy { last_writer_wins: self.conflicts.last_writer_wins.clone() },
+        }
+    }
+
+    // The HTTP server's in-process nodes; the stock ring when the file lists none
+    pub fn initial_nodes(&self) -> Vec<NodeSettings> {
+        if !self.nodes.is_empty() {
+            return self.nodes.clone();
+        }
+        (5081..=5083)
+            .map(|port| NodeSettings { address: "127.0.0.1".to_string(), port, id: None })
+            .collect()
+    }
+}
+
+// Override a number or address from the environment
+fn parsed<T: FromStr>(var: &impl Fn(&str) -> Option<String>, name: &'static str, target: &mut T) -> Result<(), ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    if let Some(value) = var(name) {
+        *target = value
+            .parse()
+            .map_err(|e: T::Err| ConfigError::Env { var: name, value: value.clone(), reason: e.to_string() })?;
+    }
+    Ok(())
+}
+
+// Override a list from a comma-separated environment variable; empty means none
+fn listed(var: &impl Fn(&str) -> Option<String>, name: &'static str, target: &mut Vec<String>) {
+    if let Some(value) = var(name) {
+        *target = value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(str::to_string).collect();
+    }
+}
+
+// Override an enum from the environment using its lowercase serde name
+fn named<T: DeserializeOwned>(var: &impl Fn(&str) -> Option<String>, name: &'static str, target: &mut T) -> Result<(), ConfigError> {
+    if let Some(value) = var(name) {
+        let deserializer: serde::de::value::StrDeserializer<serde::de::value::Error> = value.as_str().into_deserializer();
+        *target = T::deserialize(deserializer)
+            .map_err(|e| ConfigError::Env { var: name, value: value.clone(), reason: e.to_string() })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // Defaults with keys in memory, so nothing needs a database
+    fn valid() -> Settings {
+        let mut settings = Settings::default();
+        settings.storage.backend = StorageBackend::Memory;
+        settings
+    }
+
+    fn node(port: i32, id: Option<u64>) -> NodeSettings {
+        NodeSettings { address: "127.0.0.1".to_string(), port, id }
+    }
+
+    fn with_env(settings: &mut Settings, vars: &[(&str, &str)]) -> Result<(), ConfigError> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        settings.apply_env(|var| vars.get(var).cloned())
+    }
+
+    fn rejected(settings: &Settings, needle: &str) {
+        match settings.validate() {
+            Err(ConfigError::Invalid(reason)) => assert!(reason.contains(needle), "{:?} does not mention {:?}", reason, needle),
+            other => panic!("expected a rejection mentioning {:?}, got {:?}", needle, other),
+        }
+    }
+
+    #[test]
+    fn defaults_use_the_stock_ring() {
+        let settings = valid();
+        assert_eq!(settings.ring.bits, DEFAULT_RING_BITS);
+        settings.validate().unwrap();
+        assert_eq!(settings.initial_nodes().len(), 3);
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let mut settings = valid();
+        with_env(
+            &mut settings,
+            &[
+                ("DHT_RING_BITS", "16"),
+                ("DHT_SUCCESSOR_LIST_LEN", "5"),
+                ("DHT_REPLICATION_FACTOR", "2"),
+                ("DHT_RPC_TIMEOUT_MS", "750"),
+                ("DHT_TRANSFER_TIMEOUT_MS", "90000"),
+                ("DHT_TRANSPORT", "tcp"),
+                ("DHT_STORAGE_BACKEND", "embedded"),
+                ("DHT_REGISTRY", "postgres"),
+                ("DATABASE_URL", "postgres://localhost/dht"),
+                ("DHT_HTTP_BIND", "0.0.0.0:8080"),
+                ("DHT_LWW_NAMESPACES", "session, cache,,"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(settings.ring.bits, 16);
+        assert_eq!(settings.ring.successor_list_len, 5);
+        assert_eq!(settings.ring.replication_factor, 2);
+        assert_eq!(settings.timers.rpc_timeout_ms, 750);
+        assert_eq!(settings.timers.transfer_timeout_ms, 90_000);
+        assert_eq!(settings.transport.kind, TransportKind::Tcp);
+        assert_eq!(settings.storage.backend, StorageBackend::Embedded);
+        assert_eq!(settings.storage.registry_backend(), RegistryBackend::Postgres);
+        assert_eq!(settings.storage.database_url.as_deref(), Some("postgres://localhost/dht"));
+        assert_eq!(settings.http.bind, SocketAddr::from(([0, 0, 0, 0], 8080)));
+        assert_eq!(settings.conflicts.last_writer_wins, ["session", "cache"]);
+        settings.validate().unwrap();
+    }
+
+    #[test]
+    fn unset_variables_keep_the_file_values() {
+        let mut settings = valid();
+        settings.timers.stabilize_interval_ms = 123;
+        with_env(&mut settings, &[]).unwrap();
+        assert_eq!(settings.timers.stabilize_interval_ms, 123);
+        assert_eq!(settings.storage.registry, None);
+    }
+
+    #[test]
+    fn bad_environment_values_name_the_variable() {
+        for (var, value) in [
+            ("DHT_RING_BITS", "ten"),
+            ("DHT_RPC_TIMEOUT_MS", "-1"),
+            ("DHT_TRANSPORT", "udp"),
+            ("DHT_STORAGE_BACKEND", "sqlite"),
+            ("DHT_REGISTRY", "redis"),
+            ("DHT_HTTP_BIND", "localhost"),
+        ] {
+            match with_env(&mut valid(), &[(var, value)]) {
+                Err(ConfigError::Env { var: name, value: got, .. }) => assert_eq!((name, got.as_str()), (var, value)),
+                other => panic!("{}={:?} was accepted: {:?}", var, value, other),
+            }
+        }
+    }
+
+    #[test]
+    fn ring_bits_must_be_in_range() {
+        let mut settings = valid();
+        settings.ring.bits = 0;
+        rejected(&settings, "ring.bits");
+        settings.ring.bits = MAX_RING_BITS + 1;
+        rejected(&settings, "ring.bits");
+        settings.ring.bits = MAX_RING_BITS;
+        settings.validate().unwrap();
+    }
+
+    #[test]
+    fn replication_must_fit_the_successor_list() {
+        let mut settings = valid();
+        settings.ring.successor_list_len = 0;
+        rejected(&settings, "successor_list_len");
+
+        let mut settings = valid();
+        settings.ring.replication_factor = 0;
+        rejected(&settings, "replication_factor");
+        settings.ring.replication_factor = settings.ring.successor_list_len + 2;
+        rejected(&settings, "replication_factor");
+
+        let mut settings = valid();
+        settings.ring.successor_list_len = MAX_REPLICATION_FACTOR + 1;
+        settings.ring.replication_factor = MAX_REPLICATION_FACTOR + 1;
+        rejected(&settings, "at most");
+    }
+
+    #[test]
+    fn timers_must_be_positive() {
+        let mut settings = valid();
+        settings.timers.transfer_timeout_ms = 0;
+        rejected(&settings, "timers.transfer_timeout_ms");
+
+        let mut settings = valid();
+        settings.websocket.client_timeout_ms = settings.websocket.heartbeat_interval_ms;
+        rejected(&settings, "client_timeout_ms");
+    }
+
+    #[test]
+    fn storage_needs_what_its_backend_uses() {
+        rejected(&Settings::default(), "database_url");
+
+        let mut settings = valid();
+        settings.storage.backend = StorageBackend::Postgres;
+        settings.storage.database_url = Some("postgres://localhost/dht".to_string());
+        settings.storage.registry = Some(RegistryBackend::Memory);
+        rejected(&settings, "storage.registry");
+
+        let mut settings = valid();
+        settings.storage.backend = StorageBackend::Embedded;
+        settings.storage.path = PathBuf::new();
+        rejected(&settings, "storage.path");
+
+        let mut settings = valid();
+        settings.storage.max_connections = 0;
+        rejected(&settings, "max_connections");
+    }
+
+    #[test]
+    fn namespaces_must_not_contain_the_separator() {
+        let mut settings = valid();
+        settings.conflicts.last_writer_wins = vec!["session:".to_string()];
+        rejected(&settings, "last_writer_wins");
+    }
+
+    #[test]
+    fn nodes_must_be_distinct_and_off_the_http_port() {
+        let mut settings = valid();
+        settings.nodes = vec![node(5081, None), node(5081, None)];
+        rejected(&settings, "listed twice");
+
+        settings.nodes = vec![node(5081, Some(7)), node(5082, Some(7))];
+        rejected(&settings, "another node already uses");
+
+        let space = settings.space();
+        let derived = node(5081, None).identifier(&space).value();
+        settings.nodes = vec![node(5081, None), node(5082, Some(derived))];
+        rejected(&settings, "another node already uses");
+
+        settings.nodes = vec![node(5080, None)];
+        rejected(&settings, "HTTP port");
+
+        settings.nodes = vec![node(0, None)];
+        rejected(&settings, "invalid port");
+
+        settings.nodes = vec![node(5081, Some(space.size()))];
+        rejected(&settings, "must be below");
+
+        settings.nodes = vec![NodeSettings { address: String::new(), port: 5081, id: None }];
+        rejected(&settings, "address");
+
+        settings.nodes = vec![node(5081, None), node(5082, None)];
+        settings.validate().unwrap();
+    }
+}
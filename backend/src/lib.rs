@@ -0,0 +1,5 @@
+// Shared by the `backend` HTTP server and the standalone `dht-node` binary
+pub mod config { pub mod db; pub mod settings; }
+pub mod nodes;
+pub mod storage;
+pub mod ws_handler;
@@ -1,65 +1,170 @@
-use actix_web::{web, App, HttpServer, HttpResponse, Responder};
-use sqlx::PgPool;
+use actix_web::{web, App, FromRequest, HttpRequest, HttpServer, HttpResponse, Responder};
+use actix_web::http::header::{HeaderName, HeaderValue};
 use actix::prelude::*;
-use std::time::Duration;
-use serde::Deserialize;
-mod ws_handler;
-use ws_handler::{BroadcastMessage, MyWebSocket, ws_route};
+use serde::{Deserialize, Serialize};
+use backend::{config, nodes, storage, ws_handler};
+use storage::{StorageError, Stores};
+use ws_handler::Clients;
 use std::sync::{Arc, Mutex};
 use std::collections::HashSet;
 use actix::Addr;
-use actix_web::middleware::Logger;
 use std::env;
-use env_logger;
 use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::time::Instant;
 
-type Clients = Arc<Mutex<HashSet<Addr<MyWebSocket>>>>;
+use nodes::version::{context_of, live, VectorClock, Version};
+use nodes::quorum::QuorumFailure;
+use nodes::rpc::RpcError;
+use nodes::{Consistency, Identifier, IdentifierSpace, Key, QuorumError, Node, NodeConfig, NodesMap, GetNodeRef, GetNodeState, LookupMessage, LookupMode, NodeRef, PeerClient, Route, HandoffError, JoinMessage, LeaveMessage, StabilizeMessage, FixFingersMessage, InsertKeyValue, GetKeyValue, DeleteKeyValue, ReplicateData, HealthCheck};
+use config::settings::{HttpSettings, Settings};
 
-mod config { pub mod db; }
-mod nodes;
-use nodes::node_actor::{Node, JoinMessage, StabilizeMessage, FixFingersMessage, InsertKeyValue, GetKeyValue, DeleteKeyValue, ReplicateData, HealthCheck, TransferData, KeyValue, NodeRecord};
 
 
+// The {key} path segment, percent-decoded to raw bytes. Read from the undecoded URI because
+// actix's own path matching replaces escapes that are not valid UTF-8.
+struct KeyParam(Key);
+
+impl FromRequest for KeyParam {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let segment = req.uri().path().rsplit('/').next().unwrap_or_default();
+        ready(Ok(KeyParam(Key::from_url(segment))))
+    }
+}
 
 #[derive(Deserialize)]
 struct KeyValuePayload {
     value: String,
 }
 
-type NodesMap = Arc<Mutex<HashMap<i32, Addr<Node>>>>;
+// Response header naming the node that served a key request
+const SERVED_BY_HEADER: &str = "x-dht-node";
+
+// Causal context of a key: returned by reads and writes, sent back on a write to replace the versions it covers
+const CONTEXT_HEADER: &str = "x-dht-context";
+
+// Optional entry point for key requests; any known node is used when left out
+#[derive(Deserialize)]
+struct ViaQuery {
+    via: Option<Identifier>,
+}
+
+// Entry point plus how many copies must answer: ?consistency=one|quorum|all|<n>, or ?r=<n> on reads
+#[derive(Deserialize)]
+struct ReadQuery {
+    via: Option<Identifier>,
+    #[serde(default, alias = "r")]
+    consistency: Consistency,
+}
+
+// As ReadQuery, with ?w=<n> on writes
+#[derive(Deserialize)]
+struct WriteQuery {
+    via: Option<Identifier>,
+    #[serde(default, alias = "w")]
+    consistency: Consistency,
+}
+
+#[derive(Deserialize)]
+struct JoinQuery {
+    bootstrap: Option<Identifier>,
+}
+
+// Body of POST /nodes; anything left out is generated
+#[derive(Deserialize)]
+struct CreateNodePayload {
+    id: Option<Identifier>,
+    address: Option<String>,
+    port: Option<i32>,
+    bootstrap: Option<Identifier>,
+}
+
+#[derive(Deserialize)]
+struct LookupQuery {
+    via: Option<Identifier>,
+    #[serde(default)]
+    mode: LookupMode,
+    #[serde(default)]
+    trace: bool,
+}
+
+#[derive(Serialize)]
+struct LookupResponse {
+    key: Key,
+    key_id: Identifier,
+    mode: LookupMode,
+    owner: NodeRef,
+    hops: usize,
+    elapsed_us: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<Vec<Identifier>>,
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let pool = config::db::create_pool().await;
+    // dht.toml (or $DHT_CONFIG) plus DHT_* environment overrides; see dht.example.toml
+    let settings = match Settings::load(None) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let stores = match storage::open(&settings.storage).await {
+        Ok(stores) => stores,
+        Err(e) => {
+            eprintln!("Failed to open storage: {}", e);
+            std::process::exit(1);
+        }
+    };
     let clients: Clients = Arc::new(Mutex::new(HashSet::new()));
     env::set_var("RUST_LOG", "actix_web=debug");
     env_logger::init();
 
-    // Create an instance of the Node actor
-    let node = Node::new(1, "127.0.0.1".to_string(), 5080, pool.clone()).start();
-    let node2 = Node::new(2, "127.0.0.1".to_string(), 5081, pool.clone()).start();
-    let node3 = Node::new(3, "127.0.0.1".to_string(), 5082, pool.clone()).start();
+    let config = settings.node_config();
+    let space = config.space;
+    let heartbeat = settings.websocket.clone();
+    let http = settings.http.clone();
 
     // Map of node_id to Node actor address
-    let nodes_map = Arc::new(Mutex::new(HashMap::new()));;
-    nodes_map.lock().unwrap().insert(1, node.clone());
-    nodes_map.lock().unwrap().insert(2, node2.clone());
-    nodes_map.lock().unwrap().insert(3, node3.clone());
-    println!("{:?}", nodes_map.lock().unwrap());
+    let nodes_map: NodesMap = Arc::new(Mutex::new(HashMap::new()));
+
+    // Reaches key owners that run in other processes, e.g. dht-node instances that joined the ring
+    let peers = PeerClient::for_kind(config.transport, nodes_map.clone(), config.rpc_timeout, config.transfer_timeout);
 
+    // Create the initial Node actors; requests reach them through the nodes map.
+    // Ids not given in the config are derived from address:port in the identifier space
+    for node in settings.initial_nodes() {
+        let id = node.identifier(&space);
+        if let Err(e) = spawn_node(id, node.address, node.port, config.clone(), &stores, &nodes_map, &clients) {
+            eprintln!("Failed to start node {}: {}", id, e);
+            std::process::exit(1);
+        }
+    }
+    println!("{:?}", nodes_map.lock().unwrap());
 
     HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(stores.clone()))
+            .app_data(web::Data::new(space))
+            .app_data(web::Data::new(config.clone()))
             .app_data(web::Data::new(clients.clone()))
-            .app_data(web::Data::new(node.clone()))
-            .app_data(web::Data::new(node2.clone()))
-            .app_data(web::Data::new(node3.clone()))
+            .app_data(web::Data::new(heartbeat.clone()))
+            .app_data(web::Data::new(http.clone()))
             .app_data(web::Data::new(nodes_map.clone()))
+            .app_data(web::Data::new(peers.clone()))
             .route("/join/{node_id}", web::post().to(join_node))
+            .route("/leave/{node_id}", web::post().to(remove_node))
             .route("/stabilize/{node_id}", web::post().to(stabilize_node))
             .route("/fix_fingers/{node_id}", web::post().to(fix_fingers))
             .route("/nodes", web::get().to(list_nodes))
+            .route("/nodes", web::post().to(create_node))
+            .route("/nodes/{node_id}", web::get().to(node_state))
+            .route("/nodes/{node_id}", web::delete().to(remove_node))
+            .route("/lookup/{key}", web::get().to(lookup_key))
             .route("/add/{key}", web::post().to(add_key))
             .route("/get/{key}", web::get().to(get_key))
             .route("/delete/{key}", web::delete().to(delete_key))
@@ -67,102 +172,469 @@ async fn main() -> std::io::Result<()> {
             .route("/health_check/{node_id}", web::post().to(health_check))
             .route("/ws/", web::get().to(ws_handler::ws_route))
     })
-    .bind("127.0.0.1:5080")?
+    .bind(settings.http.bind)?
     .run()
     .await
 }
 
+// Start a Node actor and register it in the nodes map; fails if the node can't listen for its peers
+fn spawn_node(
+    id: Identifier,
+    address: String,
+    port: i32,
+    config: NodeConfig,
+    stores: &Stores,
+    nodes_map: &NodesMap,
+    clients: &Clients,
+) -> std::io::Result<Addr<Node>> {
+    let node = Node::new(
+        id,
+        address,
+        port,
+        config,
+        stores.registry.clone(),
+        stores.keys.clone(),
+        nodes_map.clone(),
+        clients.clone(),
+    )?
+    .start();
+    nodes_map.lock().unwrap().insert(id, node.clone());
+    Ok(node)
+}
+
+// Address of a node running in this process
+async fn node_ref(nodes_map: &NodesMap, node_id: Identifier) -> Option<NodeRef> {
+    let node = nodes_map.lock().unwrap().get(&node_id).cloned()?;
+    node.send(GetNodeRef).await.ok()
+}
+
+async fn create_node(
+    stores: web::Data<Stores>,
+    nodes_map: web::Data<NodesMap>,
+    clients: web::Data<Clients>,
+    config: web::Data<NodeConfig>,
+    http: web::Data<HttpSettings>,
+    payload: web::Json<CreateNodePayload>,
+) -> impl Responder {
+    let payload = payload.into_inner();
+    let address = payload.address.unwrap_or_else(|| "127.0.0.1".to_string());
+    let http_port = i32::from(http.bind.port());
+
+    // Without an explicit port, take the one after the highest port in use, skipping the HTTP port
+    let port = match payload.port {
+        Some(port) => port,
+        None => match stores.registry.max_port().await {
+            Ok(max_port) => match max_port.map_or(5081, |port| port + 1) {
+                port if port == http_port => port + 1,
+                port => port,
+            },
+            Err(e) => return storage_error(e),
+        },
+    };
+    // The same checks Settings::validate makes of the configured nodes
+    if !(1..=65535).contains(&port) {
+        return HttpResponse::BadRequest().body(format!("Port {} is not a valid port", port));
+    }
+    if port == http_port {
+        return HttpResponse::Conflict().body(format!("Port {} is the HTTP port", port));
+    }
+    let id = match payload.id {
+        Some(id) if id.value() >= config.space.size() => {
+            return HttpResponse::BadRequest().body(format!(
+                "Node id must be below {} for a {}-bit ring",
+                config.space.size(),
+                config.space.bits()
+            ));
+        }
+        Some(id) => id,
+        None => config.space.node_id(&address, port),
+    };
+
+    if nodes_map.lock().unwrap().contains_key(&id) {
+        return HttpResponse::Conflict().body(format!("Node {} already exists", id));
+    }
+    match stores.registry.list().await {
+        Ok(nodes) => {
+            if let Some(node) = nodes.iter().find(|node| node.address == address && node.port == i64::from(port)) {
+                return HttpResponse::Conflict().body(format!("{}:{} is already used by node {}", address, port, node.id));
+            }
+        }
+        Err(e) => return storage_error(e),
+    }
+
+    // Join through the requested bootstrap node, or any node already on the ring
+    let bootstrap = match payload.bootstrap {
+        Some(bootstrap_id) => match node_ref(&nodes_map, bootstrap_id).await {
+            Some(bootstrap) => Some(bootstrap),
+            None => return HttpResponse::NotFound().body(format!("Bootstrap node {} not found", bootstrap_id)),
+        },
+        None => {
+            let bootstrap_id = nodes_map.lock().unwrap().keys().min().copied();
+            match bootstrap_id {
+                Some(bootstrap_id) => node_ref(&nodes_map, bootstrap_id).await,
+                None => None,
+            }
+        }
+    };
+
+    let node = match spawn_node(id, address.clone(), port, config.get_ref().clone(), &stores, &nodes_map, &clients) {
+        Ok(node) => node,
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => return HttpResponse::Conflict().body(e.to_string()),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    match node.send(JoinMessage { node_id: id, bootstrap }).await {
+        Ok(Ok(())) => HttpResponse::Created().json(NodeRef { id, address, port }),
+        // The node runs on a ring of its own; POST /join/{id} can try again
+        Ok(Err(e)) => HttpResponse::ServiceUnavailable().body(format!("Node {} was started but failed to join: {}", id, e)),
+        Err(_) => HttpResponse::InternalServerError().body("Failed to communicate with the node"),
+    }
+}
+
+// Gracefully take a node off the ring, handing its keys to its successor
+async fn remove_node(
+    stores: web::Data<Stores>,
+    nodes_map: web::Data<NodesMap>,
+    path: web::Path<Identifier>,
+) -> impl Responder {
+    let node_id = path.into_inner();
+    let node = nodes_map.lock().unwrap().get(&node_id).cloned();
+    let Some(node) = node else {
+        return HttpResponse::NotFound().body(format!("Node {} not found", node_id));
+    };
+
+    match node.send(LeaveMessage).await {
+        // The node is off the ring by now, whatever the registry says
+        Ok(Ok(summary)) => match stores.registry.remove(node_id).await {
+            Ok(()) => HttpResponse::Ok().json(summary),
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                HttpResponse::InternalServerError().body(format!(
+                    "Node {} left the ring and moved {} keys, but its registry entry could not be removed: {}",
+                    node_id, summary.keys_moved, e
+                ))
+            }
+        },
+        Ok(Err(e @ HandoffError::LastNode(_))) => {
+            HttpResponse::Conflict().body(format!("Node {} cannot leave: {}", node_id, e))
+        }
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(format!("Node {} failed to leave: {}", node_id, e)),
+        Err(_) => HttpResponse::InternalServerError().body("Failed to communicate with the node"),
+    }
+}
+
 async fn join_node(
-    nodes_map: web::Data<Arc<Mutex<HashMap<i32, Addr<Node>>>>>,
-    path: web::Path<i32>,
+    nodes_map: web::Data<NodesMap>,
+    path: web::Path<Identifier>,
+    query: web::Query<JoinQuery>,
 ) -> impl Responder {
     let node_id = path.into_inner();
 
+    // Join through the requested bootstrap node, or any other node we know about
+    let bootstrap = match query.bootstrap {
+        Some(bootstrap_id) => match node_ref(&nodes_map, bootstrap_id).await {
+            Some(bootstrap) => Some(bootstrap),
+            None => return HttpResponse::NotFound().body(format!("Bootstrap node {} not found", bootstrap_id)),
+        },
+        None => {
+            let bootstrap_id = nodes_map.lock().unwrap().keys().filter(|&&id| id != node_id).min().copied();
+            match bootstrap_id {
+                Some(bootstrap_id) => node_ref(&nodes_map, bootstrap_id).await,
+                None => None,
+            }
+        }
+    };
+
     // Retrieve the appropriate Node actor for the given node_id
-    if let Some(node) = nodes_map.lock().unwrap().get(&node_id) {
-        node.send(JoinMessage { node_id }).await.unwrap();
-        HttpResponse::Ok().body(format!("Node {} joined", node_id))
-    } else {
-        HttpResponse::NotFound().body(format!("Node {} not found", node_id))
+    let node = nodes_map.lock().unwrap().get(&node_id).cloned();
+    match node {
+        Some(node) => match node.send(JoinMessage { node_id, bootstrap }).await {
+            Ok(Ok(())) => HttpResponse::Ok().body(format!("Node {} joined", node_id)),
+            Ok(Err(e)) => HttpResponse::ServiceUnavailable().body(format!("Node {} failed to join: {}", node_id, e)),
+            Err(_) => HttpResponse::InternalServerError().body("Failed to communicate with the node"),
+        },
+        None => HttpResponse::NotFound().body(format!("Node {} not found", node_id)),
     }
 }
 
 
-async fn stabilize_node(node: web::Data<Addr<Node>>, path: web::Path<i32>) -> impl Responder {
-    node.send(StabilizeMessage).await.unwrap();
-    HttpResponse::Ok().body("Node stabilized")
+async fn stabilize_node(nodes_map: web::Data<NodesMap>, path: web::Path<Identifier>) -> impl Responder {
+    let node_id = path.into_inner();
+    let node = nodes_map.lock().unwrap().get(&node_id).cloned();
+    match node {
+        Some(node) => match node.send(StabilizeMessage).await {
+            Ok(()) => HttpResponse::Ok().body("Node stabilized"),
+            Err(_) => HttpResponse::InternalServerError().body("Failed to communicate with the node"),
+        },
+        None => HttpResponse::NotFound().body(format!("Node {} not found", node_id)),
+    }
 }
 
-async fn fix_fingers(node: web::Data<Addr<Node>>, path: web::Path<i32>) -> impl Responder {
-    node.send(FixFingersMessage).await.unwrap();
-    HttpResponse::Ok().body("Finger table fixed")
+async fn fix_fingers(nodes_map: web::Data<NodesMap>, path: web::Path<Identifier>) -> impl Responder {
+    let node_id = path.into_inner();
+    let node = nodes_map.lock().unwrap().get(&node_id).cloned();
+    match node {
+        Some(node) => match node.send(FixFingersMessage).await {
+            Ok(()) => HttpResponse::Ok().body("Finger table fixed"),
+            Err(_) => HttpResponse::InternalServerError().body("Failed to communicate with the node"),
+        },
+        None => HttpResponse::NotFound().body(format!("Node {} not found", node_id)),
+    }
 }
 
-async fn list_nodes(pool: web::Data<PgPool>) -> impl Responder {
-    let result = sqlx::query_as!(
-        NodeRecord,
-        "SELECT id, address, port, predecessor FROM nodes"
-    )
-    .fetch_all(pool.get_ref())
-    .await;
-
-    match result {
+async fn list_nodes(stores: web::Data<Stores>) -> impl Responder {
+    match stores.registry.list().await {
         Ok(nodes) => HttpResponse::Ok().json(nodes),
-        Err(_) => HttpResponse::InternalServerError().body("Failed to retrieve nodes"),
+        Err(e) => storage_error(e),
     }
 }
 
 
-async fn add_key(
-    node: web::Data<Addr<Node>>,
-    path: web::Path<i32>,
-    payload: web::Json<KeyValuePayload>,
+// Node a key request enters the ring through: the requested one, or the lowest known id
+fn entry_node(nodes_map: &NodesMap, via: Option<Identifier>) -> Option<Addr<Node>> {
+    let nodes = nodes_map.lock().unwrap();
+    match via {
+        Some(id) => nodes.get(&id).cloned(),
+        None => nodes.keys().min().and_then(|id| nodes.get(id)).cloned(),
+    }
+}
+
+fn missing_entry_node(via: Option<Identifier>) -> HttpResponse {
+    match via {
+        Some(id) => HttpResponse::NotFound().body(format!("Node {} not found", id)),
+        None => HttpResponse::ServiceUnavailable().body("No nodes are running"),
+    }
+}
+
+// Look up the node responsible for `key` starting from the entry node, with its actor when it runs in this process;
+// owners elsewhere are reached through the transport
+async fn responsible_node(
+    nodes_map: &NodesMap,
+    via: Option<Identifier>,
+    key: Key,
+) -> Result<(NodeRef, Option<Addr<Node>>), HttpResponse> {
+    let entry = entry_node(nodes_map, via).ok_or_else(|| missing_entry_node(via))?;
+    let owner = match entry.send(LookupMessage { key, mode: LookupMode::Recursive }).await {
+        Ok(Some(route)) => route.owner,
+        Ok(None) => return Err(HttpResponse::ServiceUnavailable().body("No node found for key")),
+        Err(_) => return Err(HttpResponse::InternalServerError().body("Failed to communicate with the node")),
+    };
+
+    let node = nodes_map.lock().unwrap().get(&owner.id).cloned();
+    Ok((owner, node))
+}
+
+// Tag a response with the node that served it
+fn served_by(mut response: HttpResponse, node_id: Identifier) -> HttpResponse {
+    response
+        .headers_mut()
+        .insert(HeaderName::from_static(SERVED_BY_HEADER), HeaderValue::from(node_id.value()));
+    response
+}
+
+async fn lookup_key(
+    nodes_map: web::Data<NodesMap>,
+    space: web::Data<IdentifierSpace>,
+    KeyParam(key): KeyParam,
+    query: web::Query<LookupQuery>,
 ) -> impl Responder {
-    let key = path.into_inner();
-    let value = payload.value.clone();
+    let key_id = space.key_id(&key);
+    let Some(entry) = entry_node(&nodes_map, query.via) else {
+        return missing_entry_node(query.via);
+    };
+    let started = Instant::now();
+    let result = entry.send(LookupMessage { key: key.clone(), mode: query.mode }).await;
+    let elapsed_us = started.elapsed().as_micros();
 
-    // Send the InsertKeyValue message to the Node actor
-    let result = node.send(InsertKeyValue { key, value }).await;
     match result {
-        Ok(Ok(())) => HttpResponse::Ok().json("Key added"),
-        Ok(Err(e)) => {
-            eprintln!("Database error: {:?}", e);
-            HttpResponse::InternalServerError().body("Failed to add key to database")
+        Ok(Some(route)) => {
+            let hops = route.hops();
+            let Route { owner, path } = route;
+            HttpResponse::Ok().json(LookupResponse {
+                key,
+                key_id,
+                mode: query.mode,
+                owner,
+                hops,
+                elapsed_us,
+                path: query.trace.then_some(path),
+            })
         }
+        Ok(None) => HttpResponse::ServiceUnavailable().body("No node found for key"),
         Err(_) => HttpResponse::InternalServerError().body("Failed to communicate with the node"),
     }
 }
 
+async fn node_state(nodes_map: web::Data<NodesMap>, path: web::Path<Identifier>) -> impl Responder {
+    let node_id = path.into_inner();
+    let node = nodes_map.lock().unwrap().get(&node_id).cloned();
+    match node {
+        Some(node) => match node.send(GetNodeState).await {
+            Ok(state) => HttpResponse::Ok().json(state),
+            Err(_) => HttpResponse::InternalServerError().body("Failed to communicate with the node"),
+        },
+        None => HttpResponse::NotFound().body(format!("Node {} not found", node_id)),
+    }
+}
 
-async fn get_key(node: web::Data<Addr<Node>>, path: web::Path<i32>) -> impl Responder {
-    let key = path.into_inner();
-    let result = node.send(GetKeyValue { key }).await;
-    match result {
-        Ok(Ok(Some(value))) => HttpResponse::Ok().json(value),
-        Ok(Ok(None)) => HttpResponse::NotFound().body("Key not found"),
-        _ => HttpResponse::InternalServerError().body("Failed to retrieve key"),
+// The context a client sent with a write; none means a blind write, kept alongside whatever is stored
+fn request_context(req: &HttpRequest) -> Result<VectorClock, String> {
+    match req.headers().get(CONTEXT_HEADER) {
+        Some(header) => header.to_str().map_err(|e| e.to_string())?.parse(),
+        None => Ok(VectorClock::default()),
     }
 }
 
-async fn delete_key(node: web::Data<Addr<Node>>, path: web::Path<i32>) -> impl Responder {
-    let key = path.into_inner();
-    let result = node.send(DeleteKeyValue { key }).await;
-    match result {
-        Ok(Ok(())) => HttpResponse::Ok().body("Key deleted"),
-        _ => HttpResponse::InternalServerError().body("Failed to delete key"),
+// Hand the client the context of the versions it was given, to send back with its next write
+fn with_context(mut response: HttpResponse, versions: &[Version]) -> HttpResponse {
+    if let Ok(value) = HeaderValue::from_str(&context_of(versions).to_string()) {
+        response.headers_mut().insert(HeaderName::from_static(CONTEXT_HEADER), value);
     }
+    response
 }
 
-async fn replicate_data(node: web::Data<Addr<Node>>, payload: web::Json<ReplicateData>) -> impl Responder {
-    let result = node.send(payload.into_inner()).await;
-    match result {
-        Ok(Ok(())) => HttpResponse::Ok().body("Data replicated"),
-        _ => HttpResponse::InternalServerError().body("Replication failed"),
+async fn add_key(
+    req: HttpRequest,
+    nodes_map: web::Data<NodesMap>,
+    peers: web::Data<PeerClient>,
+    KeyParam(key): KeyParam,
+    query: web::Query<WriteQuery>,
+    payload: web::Json<KeyValuePayload>,
+) -> impl Responder {
+    let value = payload.value.clone();
+    let context = match request_context(&req) {
+        Ok(context) => context,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid {} header: {}", CONTEXT_HEADER, e)),
+    };
+
+    let (owner, node) = match responsible_node(&nodes_map, query.via, key.clone()).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    // Send the InsertKeyValue message to the owner
+    let msg = InsertKeyValue { key, value, context, consistency: query.consistency };
+    let result = match node {
+        Some(node) => node.send(msg).await,
+        None => Ok(peers.insert(&owner, msg).await.map_err(|e| QuorumError::Remote(owner.id, e))),
+    };
+    let response = match result {
+        Ok(Ok(versions)) => with_context(HttpResponse::Ok().json("Key added"), &versions),
+        Ok(Err(e)) => quorum_error(e),
+        Err(_) => HttpResponse::InternalServerError().body("Failed to communicate with the node"),
+    };
+    served_by(response, owner.id)
+}
+
+
+async fn get_key(
+    nodes_map: web::Data<NodesMap>,
+    peers: web::Data<PeerClient>,
+    KeyParam(key): KeyParam,
+    query: web::Query<ReadQuery>,
+) -> impl Responder {
+    let (owner, node) = match responsible_node(&nodes_map, query.via, key.clone()).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+    let msg = GetKeyValue { key, consistency: query.consistency };
+    let result = match node {
+        Some(node) => node.send(msg).await,
+        None => Ok(peers.get(&owner, msg).await.map_err(|e| QuorumError::Remote(owner.id, e))),
+    };
+    // One version is the value; concurrent writes come back as 300 Multiple Choices listing every sibling.
+    // Deletes hide the key but stay in the context, so the next write replaces them.
+    let response = match result {
+        Ok(Ok(versions)) => match live(&versions).as_slice() {
+            [] => HttpResponse::NotFound().body("Key not found"),
+            [value] => with_context(HttpResponse::Ok().json(value), &versions),
+            values => with_context(HttpResponse::MultipleChoices().json(values), &versions),
+        },
+        Ok(Err(e)) => quorum_error(e),
+        Err(_) => HttpResponse::InternalServerError().body("Failed to communicate with the node"),
+    };
+    served_by(response, owner.id)
+}
+
+async fn delete_key(
+    nodes_map: web::Data<NodesMap>,
+    peers: web::Data<PeerClient>,
+    KeyParam(key): KeyParam,
+    query: web::Query<WriteQuery>,
+) -> impl Responder {
+    let (owner, node) = match responsible_node(&nodes_map, query.via, key.clone()).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+    let msg = DeleteKeyValue { key, consistency: query.consistency };
+    let result = match node {
+        Some(node) => node.send(msg).await,
+        None => Ok(peers.delete(&owner, msg).await.map_err(|e| QuorumError::Remote(owner.id, e))),
+    };
+    let response = match result {
+        Ok(Ok(())) => HttpResponse::Ok().body("Key deleted"),
+        Ok(Err(e)) => quorum_error(e),
+        Err(_) => HttpResponse::InternalServerError().body("Failed to communicate with the node"),
+    };
+    served_by(response, owner.id)
+}
+
+// A level the ring can never meet is the caller's mistake; missing acknowledgements are the ring's
+fn quorum_error(e: QuorumError) -> HttpResponse {
+    match e {
+        QuorumError::Unsatisfiable { .. } => HttpResponse::BadRequest().body(e.to_string()),
+        QuorumError::NotMet { .. } => HttpResponse::ServiceUnavailable().body(e.to_string()),
+        QuorumError::Storage(e) => storage_error(e),
+        // An owner in another process answers with the same kinds of failure
+        QuorumError::Remote(_, RpcError::Quorum(failure)) => match failure {
+            QuorumFailure::Unsatisfiable(message) => HttpResponse::BadRequest().body(message),
+            QuorumFailure::NotMet(message) => HttpResponse::ServiceUnavailable().body(message),
+            QuorumFailure::Storage(message) => HttpResponse::InternalServerError().body(message),
+        },
+        QuorumError::Remote(owner, e) => HttpResponse::ServiceUnavailable().body(format!("Node {} is not reachable: {}", owner, e)),
     }
 }
 
-async fn health_check(node: web::Data<Addr<Node>>, path: web::Path<i32>) -> impl Responder {
-    node.send(HealthCheck).await.unwrap();
-    HttpResponse::Ok().body("Health check complete")
+// The node's store failed; the caller learns what went wrong, the log gets the details
+fn storage_error(e: StorageError) -> HttpResponse {
+    eprintln!("Database error: {:?}", e);
+    HttpResponse::InternalServerError().body(e.to_string())
+}
+
+async fn replicate_data(
+    nodes_map: web::Data<NodesMap>,
+    peers: web::Data<PeerClient>,
+    query: web::Query<ViaQuery>,
+    payload: web::Json<ReplicateData>,
+) -> impl Responder {
+    let payload = payload.into_inner();
+    let (owner, node) = match responsible_node(&nodes_map, query.via, payload.key.clone()).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+    let response = match node {
+        Some(node) => match node.send(payload).await {
+            Ok(Ok(())) => HttpResponse::Ok().body("Data replicated"),
+            Ok(Err(e)) => storage_error(e),
+            Err(_) => HttpResponse::InternalServerError().body("Failed to communicate with the node"),
+        },
+        None => match peers.replicate(&owner, payload.key, payload.versions, payload.replica).await {
+            Ok(()) => HttpResponse::Ok().body("Data replicated"),
+            Err(RpcError::Remote(message)) => HttpResponse::InternalServerError().body(message),
+            Err(e) => HttpResponse::ServiceUnavailable().body(format!("Node {} is not reachable: {}", owner.id, e)),
+        },
+    };
+    served_by(response, owner.id)
+}
+
+async fn health_check(nodes_map: web::Data<NodesMap>, path: web::Path<Identifier>) -> impl Responder {
+    let node_id = path.into_inner();
+    let node = nodes_map.lock().unwrap().get(&node_id).cloned();
+    match node {
+        Some(node) => match node.send(HealthCheck).await {
+            Ok(()) => HttpResponse::Ok().body("Health check complete"),
+            Err(_) => HttpResponse::InternalServerError().body("Failed to communicate with the node"),
+        },
+        None => HttpResponse::NotFound().body(format!("Node {} not found", node_id)),
+    }
 }
 
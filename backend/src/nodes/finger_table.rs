@@ -0,0 +1,83 @@
+use serde::Serialize;
+use super::identifier::{Identifier, IdentifierSpace};
+use super::node_actor::NodeRef;
+
+// One entry of a Chord finger table
+#[derive(Serialize, Debug, Clone)]
+pub struct Finger {
+    // (n + 2^i) mod 2^m
+    pub start: Identifier,
+    // Identifiers in [start, next start) are routed through this finger
+    pub interval: (Identifier, Identifier),
+    // First node that succeeds start
+    pub node: NodeRef,
+}
+
+// Chord finger table with one entry per bit of the identifier space; finger 0 is the successor
+#[derive(Serialize, Debug, Clone)]
+pub struct FingerTable {
+    fingers: Vec<Finger>,
+    // Index of the finger refreshed by the next fix_fingers round
+    #[serde(skip_serializing)]
+    next: usize,
+}
+
+impl FingerTable {
+    // Every finger starts out pointing at the owner, as for a ring of one
+    pub fn new(owner: &NodeRef, space: IdentifierSpace) -> Self {
+        let starts: Vec<Identifier> = (0..space.bits())
+            .map(|i| space.finger_start(owner.id, i))
+            .collect();
+
+        let fingers = starts
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| Finger {
+                start,
+                interval: (start, starts.get(i + 1).copied().unwrap_or(owner.id)),
+                node: owner.clone(),
+            })
+            .collect();
+
+        FingerTable { fingers, next: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.fingers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fingers.is_empty()
+    }
+
+    pub fn get(&self, i: usize) -> &Finger {
+        &self.fingers[i]
+    }
+
+    pub fn set(&mut self, i: usize, node: NodeRef) {
+        self.fingers[i].node = node;
+    }
+
+    // Point every finger that referenced a failed node at `replacement` until fix_fingers catches up
+    pub fn replace(&mut self, failed: Identifier, replacement: &NodeRef) {
+        for finger in self.fingers.iter_mut().filter(|finger| finger.node.id == failed) {
+            finger.node = replacement.clone();
+        }
+    }
+
+    // Index and start of the next finger to refresh, cycling through the table
+    pub fn next_to_fix(&mut self) -> (usize, Identifier) {
+        let i = self.next;
+        self.next = (self.next + 1) % self.fingers.len();
+        (i, self.fingers[i].start)
+    }
+
+    // Farthest finger that lies strictly between `owner` and `id` on the ring
+    pub fn closest_preceding_node(&self, owner: Identifier, id: Identifier) -> Option<&NodeRef> {
+        self.fingers
+            .iter()
+            .rev()
+            .map(|finger| &finger.node)
+            .find(|node| node.id.in_open_interval(owner, id))
+    }
+}
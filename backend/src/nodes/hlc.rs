@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::identifier::Identifier;
+
+// When a write happened by a node's hybrid logical clock. Ordered by wall time, then the logical
+// counter, then the writing node's id, so two writes never tie.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    // Milliseconds since the Unix epoch
+    pub wall_ms: u64,
+    // Orders events within the same millisecond, or while the wall clock is behind what we have seen
+    pub logical: u32,
+    pub node: Identifier,
+}
+
+impl Timestamp {
+    // The earliest timestamp `node` may stamp after this one
+    pub fn successor(self, node: Identifier) -> Timestamp {
+        Timestamp { wall_ms: self.wall_ms, logical: self.logical + 1, node }
+    }
+}
+
+// A node's hybrid logical clock: follows the wall clock, but never goes backwards and never falls
+// behind a timestamp the node has seen, so a write stamped here is later than any write it knew of
+#[derive(Debug, Clone, Copy)]
+pub struct HybridClock {
+    node: Identifier,
+    wall_ms: u64,
+    logical: u32,
+}
+
+fn wall_clock_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+impl HybridClock {
+    pub fn new(node: Identifier) -> Self {
+        HybridClock { node, wall_ms: 0, logical: 0 }
+    }
+
+    // Stamp a local event
+    pub fn now(&mut self) -> Timestamp {
+        self.now_at(wall_clock_ms())
+    }
+
+    // Move past a timestamp received from another node
+    pub fn observe(&mut self, seen: Timestamp) {
+        self.observe_at(seen, wall_clock_ms())
+    }
+
+    // The two above, with the wall clock reading `wall_ms`
+    fn now_at(&mut self, wall_ms: u64) -> Timestamp {
+        if wall_ms > self.wall_ms {
+            self.wall_ms = wall_ms;
+            self.logical = 0;
+        } else {
+            self.logical += 1;
+        }
+        Timestamp { wall_ms: self.wall_ms, logical: self.logical, node: self.node }
+    }
+
+    fn observe_at(&mut self, seen: Timestamp, wall_ms: u64) {
+        let wall_ms = wall_ms.max(self.wall_ms).max(seen.wall_ms);
+        self.logical = match (wall_ms == self.wall_ms, wall_ms == seen.wall_ms) {
+            (true, true) => self.logical.max(seen.logical) + 1,
+            (true, false) => self.logical + 1,
+            (false, true) => seen.logical + 1,
+            (false, false) => 0,
+        };
+        self.wall_ms = wall_ms;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(wall_ms: u64, logical: u32, node: u64) -> Timestamp {
+        Timestamp { wall_ms, logical, node: Identifier::new(node) }
+    }
+
+    #[test]
+    fn timestamps_order_by_wall_time_then_counter_then_node() {
+        assert!(stamp(2, 0, 1) > stamp(1, 9, 9));
+        assert!(stamp(1, 2, 1) > stamp(1, 1, 9));
+        assert!(stamp(1, 1, 2) > stamp(1, 1, 1));
+        assert!(stamp(1, 1, 1).successor(Identifier::new(0)) > stamp(1, 1, 1));
+    }
+
+    #[test]
+    fn follows_the_wall_clock() {
+        let mut clock = HybridClock::new(Identifier::new(1));
+        assert_eq!(clock.now_at(1_000), stamp(1_000, 0, 1));
+        assert_eq!(clock.now_at(1_000), stamp(1_000, 1, 1));
+        assert_eq!(clock.now_at(2_000), stamp(2_000, 0, 1));
+    }
+
+    #[test]
+    fn stays_monotonic_when_the_wall_clock_goes_back() {
+        let mut clock = HybridClock::new(Identifier::new(1));
+        let mut last = clock.now_at(5_000);
+        for wall_ms in [4_000, 4_999, 0, 5_000] {
+            let next = clock.now_at(wall_ms);
+            assert!(next > last, "{:?} after {:?}", next, last);
+            last = next;
+        }
+        assert_eq!(last.wall_ms, 5_000);
+    }
+
+    #[test]
+    fn stamps_after_every_timestamp_observed() {
+        // A remote stamp ahead of our wall clock
+        let mut clock = HybridClock::new(Identifier::new(1));
+        let remote = stamp(9_000, 4, 2);
+        clock.observe_at(remote, 1_000);
+        assert!(clock.now_at(1_000) > remote);
+
+        // One from the same millisecond with a higher counter, from a node with a higher id
+        let mut clock = HybridClock::new(Identifier::new(1));
+        clock.now_at(3_000);
+        let remote = stamp(3_000, 7, 2);
+        clock.observe_at(remote, 3_000);
+        assert!(clock.now_at(3_000) > remote);
+
+        // An older one leaves the clock ahead of where it was
+        let mut clock = HybridClock::new(Identifier::new(1));
+        let before = clock.now_at(3_000);
+        clock.observe_at(stamp(1_000, 50, 2), 3_000);
+        assert!(clock.now_at(3_000) > before);
+    }
+}
@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::fmt;
+use super::key::Key;
+
+// Default number of bits in the identifier space (a ring of 2^10 = 1024 positions)
+pub const DEFAULT_RING_BITS: u32 = 10;
+
+// Identifiers are stored as BIGINT in Postgres, so the ring can be at most 63 bits wide
+pub const MAX_RING_BITS: u32 = 63;
+
+// Position of a node or key on the Chord ring
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(transparent)]
+pub struct Identifier(u64);
+
+impl Identifier {
+    pub fn new(value: u64) -> Self {
+        Identifier(value)
+    }
+
+    pub fn value(self) -> u64 {
+        self.0
+    }
+
+    // True if self lies strictly between start and end going clockwise.
+    // When start == end the interval covers the whole ring except that point.
+    pub fn in_open_interval(self, start: Identifier, end: Identifier) -> bool {
+        if start < end {
+            start < self && self < end
+        } else if start > end {
+            self > start || self < end
+        } else {
+            self != start
+        }
+    }
+
+    // True if self lies in (start, end] going clockwise.
+    // When start == end the interval covers the whole ring.
+    pub fn in_half_open_interval(self, start: Identifier, end: Identifier) -> bool {
+        self == end || self.in_open_interval(start, end)
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<Identifier> for i64 {
+    fn from(id: Identifier) -> i64 {
+        id.0 as i64
+    }
+}
+
+impl From<i64> for Identifier {
+    fn from(value: i64) -> Identifier {
+        Identifier(value as u64)
+    }
+}
+
+// An m-bit identifier space: hashing into it and modular arithmetic on it
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdentifierSpace {
+    bits: u32,
+}
+
+impl IdentifierSpace {
+    pub fn new(bits: u32) -> Self {
+        assert!(
+            (1..=MAX_RING_BITS).contains(&bits),
+            "ring bits must be between 1 and {}",
+            MAX_RING_BITS
+        );
+        IdentifierSpace { bits }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    // Number of positions on the ring (2^m)
+    pub fn size(&self) -> u64 {
+        1u64 << self.bits
+    }
+
+    // SHA-1 the input and keep the top m bits of the digest
+    pub fn hash(&self, bytes: &[u8]) -> Identifier {
+        let digest = Sha1::digest(bytes);
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&digest[..8]);
+        Identifier(u64::from_be_bytes(prefix) >> (64 - self.bits))
+    }
+
+    // Node identifiers are derived from the node's "address:port"
+    pub fn node_id(&self, address: &str, port: i32) -> Identifier {
+        self.hash(format!("{}:{}", address, port).as_bytes())
+    }
+
+    // Keys are placed by their raw bytes, so the key "12" lands where the old integer key 12 did
+    pub fn key_id(&self, key: &Key) -> Identifier {
+        self.hash(key.as_bytes())
+    }
+
+    // (id + offset) mod 2^m
+    pub fn add(&self, id: Identifier, offset: u64) -> Identifier {
+        Identifier(id.0.wrapping_add(offset) & (self.size() - 1))
+    }
+
+    // Start of the i-th finger of `id`: (id + 2^i) mod 2^m
+    pub fn finger_start(&self, id: Identifier, i: u32) -> Identifier {
+        self.add(id, 1u64 << i)
+    }
+}
+
+impl Default for IdentifierSpace {
+    fn default() -> Self {
+        IdentifierSpace::new(DEFAULT_RING_BITS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(value: u64) -> Identifier {
+        Identifier::new(value)
+    }
+
+    #[test]
+    fn open_interval_without_wrap() {
+        assert!(id(5).in_open_interval(id(3), id(8)));
+        assert!(!id(3).in_open_interval(id(3), id(8)));
+        assert!(!id(8).in_open_interval(id(3), id(8)));
+        assert!(!id(9).in_open_interval(id(3), id(8)));
+    }
+
+    #[test]
+    fn open_interval_wraps_past_zero() {
+        assert!(id(1020).in_open_interval(id(1000), id(10)));
+        assert!(id(0).in_open_interval(id(1000), id(10)));
+        assert!(id(9).in_open_interval(id(1000), id(10)));
+        assert!(!id(10).in_open_interval(id(1000), id(10)));
+        assert!(!id(1000).in_open_interval(id(1000), id(10)));
+        assert!(!id(500).in_open_interval(id(1000), id(10)));
+    }
+
+    #[test]
+    fn open_interval_with_equal_endpoints_is_everything_but_the_endpoint() {
+        assert!(id(0).in_open_interval(id(7), id(7)));
+        assert!(id(1023).in_open_interval(id(7), id(7)));
+        assert!(!id(7).in_open_interval(id(7), id(7)));
+    }
+
+    #[test]
+    fn half_open_interval_includes_the_end_only() {
+        assert!(id(8).in_half_open_interval(id(3), id(8)));
+        assert!(!id(3).in_half_open_interval(id(3), id(8)));
+        assert!(id(10).in_half_open_interval(id(1000), id(10)));
+        assert!(!id(1000).in_half_open_interval(id(1000), id(10)));
+    }
+
+    #[test]
+    fn half_open_interval_with_equal_endpoints_is_the_whole_ring() {
+        for value in [0, 6, 7, 8, 1023] {
+            assert!(id(value).in_half_open_interval(id(7), id(7)));
+        }
+    }
+
+    #[test]
+    fn hash_keeps_the_top_bits_of_the_digest() {
+        // SHA-1("abc") starts with a9 99 3e 36 47 06 81 6a
+        let space = IdentifierSpace::new(10);
+        assert_eq!(space.hash(b"abc"), id(0xa999_3e36_4706_816a >> 54));
+        assert_eq!(IdentifierSpace::new(1).hash(b"abc"), id(1));
+        assert_eq!(IdentifierSpace::new(MAX_RING_BITS).hash(b"abc"), id(0xa999_3e36_4706_816a >> 1));
+    }
+
+    #[test]
+    fn hash_stays_inside_the_ring() {
+        for bits in [1, 10, 32, MAX_RING_BITS] {
+            let space = IdentifierSpace::new(bits);
+            for key in ["", "a", "12", "users:42"] {
+                assert!(space.hash(key.as_bytes()).value() < space.size());
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn ring_wider_than_max_bits_is_rejected() {
+        IdentifierSpace::new(MAX_RING_BITS + 1);
+    }
+
+    #[test]
+    fn add_and_finger_start_wrap_modulo_ring_size() {
+        let space = IdentifierSpace::new(10);
+        assert_eq!(space.add(id(1020), 10), id(6));
+        assert_eq!(space.finger_start(id(1000), 9), id(488));
+        let widest = IdentifierSpace::new(MAX_RING_BITS);
+        assert_eq!(widest.add(id(widest.size() - 1), 1), id(0));
+        assert_eq!(widest.finger_start(id(0), MAX_RING_BITS - 1), id(1 << 62));
+    }
+}
@@ -0,0 +1,110 @@
+use percent_encoding::{percent_decode_str, percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+
+// Bytes left as they are in a key's URL form; the same set encodeURIComponent leaves alone
+const URL_SAFE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'!')
+    .remove(b'~')
+    .remove(b'*')
+    .remove(b'\'')
+    .remove(b'(')
+    .remove(b')');
+
+// Application key: any byte string. It is placed on the ring by hashing its bytes and stored as is.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Key(Vec<u8>);
+
+impl Key {
+    pub fn new(bytes: impl Into<Vec<u8>>) -> Self {
+        Key(bytes.into())
+    }
+
+    // Decode the percent-encoded form used in URL paths and JSON; anything not escaped is taken literally
+    pub fn from_url(encoded: &str) -> Self {
+        Key(percent_decode_str(encoded).collect())
+    }
+
+    // Percent-encoded form, safe to put in a URL path segment
+    pub fn to_url(&self) -> String {
+        percent_encode(&self.0, URL_SAFE).to_string()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    // The part before the first ':', as in "users:42"; keys without one are in no namespace
+    pub fn namespace(&self) -> Option<&[u8]> {
+        self.0.iter().position(|&b| b == b':').map(|end| &self.0[..end])
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_url())
+    }
+}
+
+impl From<&str> for Key {
+    fn from(key: &str) -> Self {
+        Key::new(key)
+    }
+}
+
+impl From<Vec<u8>> for Key {
+    fn from(bytes: Vec<u8>) -> Self {
+        Key(bytes)
+    }
+}
+
+// JSON carries the URL form; binary formats such as the wire protocol carry the raw bytes
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_url())
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(KeyVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(KeyVisitor)
+        }
+    }
+}
+
+struct KeyVisitor;
+
+impl<'de> Visitor<'de> for KeyVisitor {
+    type Value = Key;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a percent-encoded string or a byte string")
+    }
+
+    fn visit_str<E: de::Error>(self, encoded: &str) -> Result<Key, E> {
+        Ok(Key::from_url(encoded))
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Key, E> {
+        Ok(Key::new(bytes))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Key, E> {
+        Ok(Key(bytes))
+    }
+}
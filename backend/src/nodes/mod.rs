@@ -1,8 +1,20 @@
 // src/nodes/mod.rs
 
 // Declare the module within nodes
+pub mod finger_table;
+pub mod hlc;
+pub mod identifier;
+pub mod key;
 pub mod node_actor;
+pub mod quorum;
+pub mod rpc;
+pub mod transport;
+pub mod version;
+pub mod wire;
 
 // Re-export structs for easy access
-// pub use node_actor::{Node, JoinMessage, StabilizeMessage, FixFingersMessage, LookupMessage, HealthCheck, FingerTableUpdate};
-pub use node_actor::{Node, JoinMessage, StabilizeMessage, FixFingersMessage, InsertKeyValue, GetKeyValue, DeleteKeyValue, ReplicateData, HealthCheck, TransferData, KeyValue, NodeRecord};
+pub use identifier::{Identifier, IdentifierSpace};
+pub use key::Key;
+pub use node_actor::{Node, NodeConfig, NodesMap, GetNodeRef, GetNodeState, LookupMessage, LookupMode, NodeRef, Route, HandoffError, JoinError, JoinMessage, LeaveMessage, StabilizeMessage, FixFingersMessage, InsertKeyValue, GetKeyValue, DeleteKeyValue, ReplicateData, HealthCheck};
+pub use quorum::{Consistency, QuorumError};
+pub use transport::{PeerClient, TransportKind};
@@ -1,101 +1,559 @@
-use actix_web::{web, App, HttpServer, Responder};
-use actix::spawn;
 use actix::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use sqlx::{PgPool, Error, query};
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use super::finger_table::FingerTable;
+use super::identifier::{Identifier, IdentifierSpace};
+use super::key::Key;
+use super::quorum::{gather, Consistency, QuorumError};
+use super::rpc::{self, RpcError};
+use super::transport::{PeerClient, TransportKind};
+use super::hlc::HybridClock;
+use super::version::{context_of, ConflictPolicy, VectorClock, Version};
+use crate::storage::{Change, KeyValue, NodeRegistry, Replica, Storage, StorageError, ALL_KEYS};
+use crate::ws_handler::{broadcast, Clients, NodeEvent, PeerRole};
+
+// Map of node_id to Node actor address, shared by every node in the process
+pub type NodesMap = Arc<Mutex<HashMap<Identifier, Addr<Node>>>>;
+
+// Ring and timer settings for a node
+#[derive(Serialize, Debug, Clone)]
+pub struct NodeConfig {
+    pub space: IdentifierSpace,
+    // How often the node runs Chord stabilization against its successor
+    pub stabilize_interval: Duration,
+    // How often the node refreshes one finger table entry
+    pub fix_fingers_interval: Duration,
+    // Number of successors (r) each node keeps to survive successor failures
+    pub successor_list_len: usize,
+    // Copies of each key, counting the owner's
+    pub replication_factor: usize,
+    // How long to wait for a peer before treating it as failed
+    pub rpc_timeout: Duration,
+    // How long to wait for a peer to take over a batch of keys
+    pub transfer_timeout: Duration,
+    // How often the node pings its predecessor and successor
+    pub check_predecessor_interval: Duration,
+    // How the node reaches its peers
+    pub transport: TransportKind,
+    // Where to listen for peers when that differs from the advertised address (e.g. 0.0.0.0 behind NAT)
+    pub bind: Option<SocketAddr>,
+    // How concurrent writes are settled in each key namespace
+    pub conflicts: ConflictPolicy,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            space: IdentifierSpace::default(),
+            stabilize_interval: Duration::from_secs(5),
+            fix_fingers_interval: Duration::from_secs(60),
+            successor_list_len: 3,
+            replication_factor: 3,
+            rpc_timeout: Duration::from_secs(2),
+            transfer_timeout: Duration::from_secs(60),
+            check_predecessor_interval: Duration::from_secs(5),
+            transport: TransportKind::default(),
+            bind: None,
+            conflicts: ConflictPolicy::default(),
+        }
+    }
+}
 
 #[derive(Serialize, Debug, Clone)]
 pub struct Node {
-    pub id: i32,
+    pub id: Identifier,
     pub address: String,
     pub port: i32,
-    pub predecessor: Option<i32>,
-    pub fingers: HashMap<i32, i32>,
+    pub predecessor: Option<NodeRef>,
+    // The next r nodes clockwise; empty while the node is alone on the ring
+    pub successors: Vec<NodeRef>,
+    pub fingers: FingerTable,
+    pub config: NodeConfig,
+    // Where the node records itself and what it learns about its neighbours
+    #[serde(skip_serializing, skip_deserializing)]
+    pub registry: Arc<dyn NodeRegistry>,
+    // Where this node's key-value rows live
+    #[serde(skip_serializing, skip_deserializing)]
+    pub storage: Arc<dyn Storage>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub nodes: NodesMap,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub clients: Clients,
+    #[serde(skip_serializing, skip_deserializing)]
+    peers: PeerClient,
+    // Stamps the writes this node coordinates
+    #[serde(skip_serializing, skip_deserializing)]
+    clock: HybridClock,
+    // Bound by `new` and handed to the RPC server once the actor starts
     #[serde(skip_serializing, skip_deserializing)]
-    pub db_pool: PgPool, // Add PgPool here
+    listener: Arc<Mutex<Option<TcpListener>>>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct NodeRecord {
-    pub id: i64,
+// Id and network address of a node on the ring
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeRef {
+    pub id: Identifier,
     pub address: String,
-    pub port: i64,
-    pub predecessor: Option<i64>,
+    pub port: i32,
+}
+
+// How a lookup travels around the ring
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LookupMode {
+    // The node that starts the lookup contacts each hop itself
+    Iterative,
+    // Each node forwards the lookup to the next hop
+    #[default]
+    Recursive,
 }
 
+// Outcome of a lookup: the owning node and the ids of the nodes visited on the way
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct KeyValue {
-    pub key: i32,
-    pub value: String,
+pub struct Route {
+    pub owner: NodeRef,
+    pub path: Vec<Identifier>,
+}
+
+impl Route {
+    pub fn hops(&self) -> usize {
+        self.path.len().saturating_sub(1)
+    }
+}
+
+// Answer to a single iterative routing step
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum NextHop {
+    Done(NodeRef),
+    Forward(NodeRef),
 }
 
 
 
+
 impl Node {
-    pub fn new(id: i32, address: String, port: i32, db_pool: PgPool) -> Self {
-        // Insert the node into the nodes table
-        let pool = db_pool.clone();
-        let address_clone = address.clone();
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: Identifier,
+        address: String,
+        port: i32,
+        config: NodeConfig,
+        registry: Arc<dyn NodeRegistry>,
+        storage: Arc<dyn Storage>,
+        nodes: NodesMap,
+        clients: Clients,
+    ) -> io::Result<Self> {
+        // Listen before anyone learns about us, so a node its peers can't reach never joins the ring
+        let listener = bind_rpc_listener(id, &config, &address, port)?;
+
+        // Record the node in the registry
+        let register = registry.register(id, address.clone(), port);
         actix::spawn(async move {
-            query!(
-                "INSERT INTO nodes (id, address, port) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
-                id as i32,
-                address_clone,
-                port as i32
-            )
-            .execute(&pool)
-            .await
-            .expect("Failed to insert node into nodes table");
+            if let Err(e) = register.await {
+                println!("Failed to register node {}: {}", id, e);
+            }
         });
 
-        Node {
+        // A node starts out as a ring of one: every finger, including the successor, is itself
+        let fingers = FingerTable::new(
+            &NodeRef {
+                id,
+                address: address.clone(),
+                port,
+            },
+            config.space,
+        );
+
+        let peers = PeerClient::for_kind(config.transport, nodes.clone(), config.rpc_timeout, config.transfer_timeout);
+
+        Ok(Node {
             id,
             address,
             port,
             predecessor: None,
-            fingers: HashMap::new(),
-            db_pool,
+            successors: Vec::new(),
+            fingers,
+            config,
+            registry,
+            storage,
+            nodes,
+            clients,
+            peers,
+            clock: HybridClock::new(id),
+            listener: Arc::new(Mutex::new(listener)),
+        })
+    }
+
+    pub fn node_ref(&self) -> NodeRef {
+        NodeRef {
+            id: self.id,
+            address: self.address.clone(),
+            port: self.port,
+        }
+    }
+
+    pub fn successor(&self) -> NodeRef {
+        self.successors.first().cloned().unwrap_or_else(|| self.node_ref())
+    }
+
+    // Answer peers on the socket bound in `new`; the loopback transport has none
+    fn start_rpc_server(&self, ctx: &mut Context<Self>) {
+        let Some(listener) = self.listener.lock().unwrap().take() else {
+            return;
+        };
+        match tokio::net::TcpListener::from_std(listener) {
+            Ok(listener) => {
+                ctx.spawn(rpc::serve(ctx.address(), listener).into_actor(self));
+            }
+            Err(e) => println!("Node {} failed to serve peers: {:?}", self.id, e),
         }
     }
 
+    fn schedule_stabilization(&self, ctx: &mut Context<Self>) {
+        ctx.run_interval(self.config.stabilize_interval, |_node, ctx| {
+            ctx.notify(StabilizeMessage);
+        });
+    }
+
+    fn schedule_health_check(&self, ctx: &mut Context<Self>) {
+        ctx.run_interval(self.config.check_predecessor_interval, |_node, ctx| {
+            ctx.notify(HealthCheck);
+        });
+    }
+
+    // Record a peer's liveness in the nodes table and tell WebSocket clients about it
+    fn record_health(&self, peer_id: Identifier, role: PeerRole, alive: bool) {
+        let event = if alive {
+            NodeEvent::PeerAlive { node_id: self.id, peer_id, role }
+        } else {
+            NodeEvent::PeerFailed { node_id: self.id, peer_id, role }
+        };
+        broadcast(&self.clients, &event);
+
+        let status = if alive { "alive" } else { "failed" };
+        let update = self.registry.set_status(peer_id, status);
+        actix::spawn(async move {
+            if let Err(e) = update.await {
+                println!("Failed to record status of node {}: {}", peer_id, e);
+            }
+        });
+    }
+
+    // Switch to `candidate` if it sits between us and our current successor
+    fn adopt_successor(&mut self, candidate: NodeRef) {
+        let successor = self.successor();
+        if candidate.id == successor.id || candidate.id == self.id {
+            return;
+        }
+        if successor.id == self.id || candidate.id.in_open_interval(self.id, successor.id) {
+            println!("Node {} adopting successor {}", self.id, candidate.id);
+            let mut successors = vec![candidate];
+            successors.extend(self.successors.iter().cloned());
+            self.set_successors(successors);
+        }
+    }
+
+    // Replace the successor list, keeping finger 0 and the nodes table in step with its head
+    fn set_successors(&mut self, successors: Vec<NodeRef>) {
+        let mut list: Vec<NodeRef> = Vec::with_capacity(self.config.successor_list_len);
+        for successor in successors {
+            // Stop once the list wraps back around to us
+            if successor.id == self.id {
+                break;
+            }
+            if list.iter().any(|known| known.id == successor.id) {
+                continue;
+            }
+            list.push(successor);
+            if list.len() == self.config.successor_list_len {
+                break;
+            }
+        }
+
+        let previous = self.successor();
+        let previous_holders: Vec<Identifier> = self.replica_holders().iter().map(|s| s.id).collect();
+        self.successors = list;
+        if self.replica_holders().iter().map(|s| s.id).ne(previous_holders) {
+            self.refresh_replicas();
+        }
+        let successor = self.successor();
+        if successor.id == previous.id {
+            return;
+        }
+
+        self.fingers.set(0, successor.clone());
+        let node_id = self.id;
+        let update = self.registry.set_successor(node_id, successor.id);
+        actix::spawn(async move {
+            if let Err(e) = update.await {
+                println!("Failed to record successor of node {}: {}", node_id, e);
+            }
+        });
+    }
+
+    // Drop a failed node from the successor list and from any finger that points at it
+    fn evict_peer(&mut self, node_id: Identifier) {
+        let successors: Vec<NodeRef> = self.successors.iter().filter(|s| s.id != node_id).cloned().collect();
+        self.set_successors(successors);
+        let replacement = self.successor();
+        self.fingers.replace(node_id, &replacement);
+    }
+
+    // Refresh one finger per tick, as in the Chord paper
     fn schedule_finger_table_update(&self, ctx: &mut Context<Self>) {
-        ctx.run_interval(Duration::from_secs(60), |node, _ctx| {
-            node.update_finger_table();
+        ctx.run_interval(self.config.fix_fingers_interval, |node, ctx| {
+            node.fix_next_finger(ctx);
         });
     }
 
-    fn update_finger_table(&mut self) {
-        for i in 0..self.fingers.len() as i32 {
-            let target_id = (self.id + 2_i32.pow(i as u32)) % 1024; // Example ring size
-            if let Some(closest_node) = self.closest_preceding_node(target_id) {
-                self.fingers.insert(i, closest_node);
+    // fix_fingers: look up the start of the next finger and point the finger at its successor
+    fn fix_next_finger(&mut self, ctx: &mut Context<Self>) {
+        let (i, start) = self.fingers.next_to_fix();
+        let lookup = self.find_successor(start, Vec::new());
+        ctx.spawn(lookup.into_actor(self).map(move |route, node, _| {
+            match route {
+                // Finger 0 is the successor, which stabilization owns
+                Some(route) if i == 0 => node.adopt_successor(route.owner),
+                Some(route) => node.fingers.set(i, route.owner),
+                None => {}
             }
+        }));
+    }
+
+    // Responsible for keys in (predecessor, self]; without a predecessor only a lone node knows it owns the key
+    fn is_responsible_for(&self, key_id: Identifier) -> bool {
+        match &self.predecessor {
+            Some(predecessor) => key_id.in_half_open_interval(predecessor.id, self.id),
+            None => self.successors.is_empty(),
+        }
+    }
+
+    // Successors holding copies of the keys this node owns, in rank order
+    fn replica_holders(&self) -> Vec<NodeRef> {
+        self.successors
+            .iter()
+            .filter(|s| s.id != self.id)
+            .take(self.config.replication_factor.saturating_sub(1))
+            .cloned()
+            .collect()
+    }
+
+    // Apply a write we coordinate to our own copy, then copy the result to the replica holders; answers
+    // with the key's versions once `required` copies, ours included, have it
+    fn coordinate_write(&self, key: Key, required: usize, change: Change) -> ResponseFuture<Result<Vec<Version>, QuorumError>> {
+        let node_id = self.id;
+        let update = self.storage.update(node_id, key.clone(), Replica::owned_by(node_id), change);
+        let peers = self.peers.clone();
+        let holders = self.replica_holders();
+
+        Box::pin(async move {
+            let versions = update.await?;
+            // Copies for the next replication_factor - 1 successors, the i-th tagged with rank i;
+            // each merges our versions into its own the way the key's namespace asks
+            let writes: Vec<_> = holders
+                .into_iter()
+                .zip(1..=u8::MAX)
+                .map(|(peer, rank)| {
+                    let peers = peers.clone();
+                    let (key, versions) = (key.clone(), versions.clone());
+                    let replica = Replica { owner: node_id, rank };
+                    (peer.id, async move {
+                        let result = peers.replicate(&peer, key.clone(), versions, replica).await;
+                        if let Err(e) = &result {
+                            println!("Node {}: failed to replicate key {} to {}: {}", node_id, key, peer.id, e);
+                        }
+                        result
+                    })
+                })
+                .collect();
+            gather(writes, required - 1, required).await?;
+            Ok(versions)
+        })
+    }
+
+    // Push rows this node owns to its replica holders
+    fn replicate_rows(&self, rows: Vec<KeyValue>) {
+        if rows.is_empty() {
+            return;
         }
-        println!("Node {}: Finger table updated.", self.id);
+        actix::spawn(push_replicas(self.peers.clone(), self.id, self.replica_holders(), rows));
     }
 
-    fn closest_preceding_node(&self, id: i32) -> Option<i32> {
-        // Collect fingers into a Vec and reverse for ordered access
-        let fingers_vec: Vec<_> = self.fingers.iter().collect();
-        fingers_vec.iter().rev().find_map(|(_, &node_id)| {
-            if node_id < id {
-                Some(node_id)
-            } else {
-                None
+    // Our replica holders changed: copy every key we own to the current ones
+    fn refresh_replicas(&self) {
+        let scan = self.storage.scan(self.id, ALL_KEYS);
+        let peers = self.peers.clone();
+        let node_id = self.id;
+        let holders = self.replica_holders();
+        actix::spawn(async move {
+            let owned = Replica::owned_by(node_id);
+            match scan.await {
+                Ok(rows) => {
+                    let rows: Vec<KeyValue> = rows.into_iter().filter(|kv| kv.replica == owned).collect();
+                    push_replicas(peers, node_id, holders, rows).await;
+                }
+                Err(e) => println!("Node {}: failed to scan keys to replicate: {}", node_id, e),
+            }
+        });
+    }
+
+    // Rows we hold for keys that now fall in our range but were owned elsewhere become ours.
+    // This is how a replica takes over when its owner fails; they are then copied on to our own holders.
+    fn promote_replicas(&self, ctx: &mut Context<Self>) {
+        let scan = self.storage.scan(self.id, ALL_KEYS);
+        ctx.spawn(scan.into_actor(self).map(|rows, node, _| {
+            let rows = match rows {
+                Ok(rows) => rows,
+                Err(e) => {
+                    println!("Node {}: failed to scan keys for promotion: {}", node.id, e);
+                    return;
+                }
+            };
+            let owned = Replica::owned_by(node.id);
+            let space = node.config.space;
+            let promoted: Vec<KeyValue> = rows
+                .into_iter()
+                .filter(|kv| kv.replica != owned && node.is_responsible_for(space.key_id(&kv.key)))
+                .map(|kv| KeyValue { replica: owned, ..kv })
+                .collect();
+            if promoted.is_empty() {
+                return;
+            }
+
+            println!("Node {} promoted {} replicas to owned keys", node.id, promoted.len());
+            for kv in &promoted {
+                let node_id = node.id;
+                let (key, versions, conflicts) = (kv.key.clone(), kv.versions.clone(), node.config.conflicts.clone());
+                let retag = node.storage.update(
+                    node_id,
+                    kv.key.clone(),
+                    owned,
+                    Box::new(move |current| conflicts.merge(&key, current, versions)),
+                );
+                actix::spawn(async move {
+                    if let Err(e) = retag.await {
+                        println!("Node {}: failed to promote a replica: {}", node_id, e);
+                    }
+                });
+            }
+            broadcast(
+                &node.clients,
+                &NodeEvent::ReplicasPromoted { node_id: node.id, keys_promoted: promoted.len() },
+            );
+            node.replicate_rows(promoted);
+        }));
+    }
+
+    // One routing step for `id` at this node: either we know the owner or we name the next hop
+    fn next_hop(&self, id: Identifier) -> NextHop {
+        if self.is_responsible_for(id) {
+            return NextHop::Done(self.node_ref());
+        }
+
+        let successor = self.successor();
+        if id.in_half_open_interval(self.id, successor.id) {
+            return NextHop::Done(successor);
+        }
+
+        match self.fingers.closest_preceding_node(self.id, id) {
+            Some(next) => NextHop::Forward(next.clone()),
+            None => NextHop::Done(successor),
+        }
+    }
+
+    // Recursive Chord find_successor: each node appends itself to the path and forwards the request
+    fn find_successor(&self, id: Identifier, mut path: Vec<Identifier>) -> ResponseFuture<Option<Route>> {
+        path.push(self.id);
+        let next = match self.next_hop(id) {
+            NextHop::Done(owner) => return Box::pin(async move { Some(Route { owner, path }) }),
+            NextHop::Forward(next) => next,
+        };
+
+        let node_id = self.id;
+        let peers = self.peers.clone();
+        Box::pin(async move {
+            match peers.find_successor(&next, id, path).await {
+                Ok(route) => route,
+                Err(e) => {
+                    println!("Node {}: next hop {} for {} failed: {}", node_id, next.id, id, e);
+                    None
+                }
             }
         })
     }
+
+    // Iterative Chord find_successor: this node asks every hop for the next one itself
+    fn iterative_find_successor(&self, id: Identifier) -> ResponseFuture<Option<Route>> {
+        let node_id = self.id;
+        let peers = self.peers.clone();
+        let mut hop = self.next_hop(id);
+        let mut path = vec![self.id];
+
+        Box::pin(async move {
+            loop {
+                let next = match hop {
+                    NextHop::Done(owner) => return Some(Route { owner, path }),
+                    NextHop::Forward(next) => next,
+                };
+
+                path.push(next.id);
+                hop = match peers.next_hop(&next, id).await {
+                    Ok(hop) => hop,
+                    Err(e) => {
+                        println!("Node {}: next hop {} for {} failed: {}", node_id, next.id, id, e);
+                        return None;
+                    }
+                };
+            }
+        })
+    }
+}
+
+// Bind the socket peers reach the node on: the configured bind address, else its own address.
+// Nothing to bind when peers are actors in this process.
+fn bind_rpc_listener(id: Identifier, config: &NodeConfig, address: &str, port: i32) -> io::Result<Option<TcpListener>> {
+    if config.transport != TransportKind::Tcp {
+        return Ok(None);
+    }
+    let bind = match config.bind {
+        Some(bind) => bind.to_string(),
+        None => format!("{}:{}", address, port),
+    };
+    let listener = TcpListener::bind(bind.as_str())
+        .map_err(|e| io::Error::new(e.kind(), format!("node {} cannot listen for peers on {}: {}", id, bind, e)))?;
+    listener.set_nonblocking(true)?;
+    println!("Node {} listening for peers on {}", id, bind);
+    Ok(Some(listener))
 }
 
 // Messages for various DHT functions
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Result<(), JoinError>")]
 pub struct JoinMessage {
-    pub node_id: i32,
+    pub node_id: Identifier,
+    pub bootstrap: Option<NodeRef>,
+}
+
+// Why a node could not join the ring through its bootstrap node; it is left on a ring of its own
+#[derive(Debug)]
+pub enum JoinError {
+    // The bootstrap node could not be asked for our successor
+    Peer(Identifier, RpcError),
+    // The bootstrap node answered but found no successor for us
+    NoSuccessor(Identifier),
+}
+
+impl std::fmt::Display for JoinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinError::Peer(id, e) => write!(f, "bootstrap node {}: {}", id, e),
+            JoinError::NoSuccessor(id) => write!(f, "bootstrap node {} found no successor", id),
+        }
+    }
 }
 
 #[derive(Message)]
@@ -107,109 +565,310 @@ pub struct StabilizeMessage;
 pub struct FixFingersMessage;
 
 #[derive(Message)]
-#[rtype(result = "Option<i32>")]
+#[rtype(result = "Option<Route>")]
 pub struct LookupMessage {
-    pub key: i32,
+    pub key: Key,
+    pub mode: LookupMode,
 }
 
-#[derive(Message)]
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Option<Route>")]
+pub struct FindSuccessor {
+    pub id: Identifier,
+    pub path: Vec<Identifier>,
+}
+
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "NextHop")]
+pub struct NextHopMessage {
+    pub id: Identifier,
+}
+
+#[derive(Message, Serialize, Deserialize)]
 #[rtype(result = "()")]
 pub struct NotifyJoin {
-    pub new_node_id: i32,
+    pub new_node: NodeRef,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct UpdateSuccessor {
-    pub new_successor_id: i32,
+    pub new_successor: NodeRef,
 }
 
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Option<NodeRef>")]
+pub struct GetPredecessor;
+
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Vec<NodeRef>")]
+pub struct GetSuccessorList;
+
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "NodeRef")]
+pub struct GetNodeRef;
+
+#[derive(Message)]
+#[rtype(result = "serde_json::Value")]
+pub struct GetNodeState;
+
 // Implementing the Actor trait for Node
 impl Actor for Node {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.start_rpc_server(ctx);
+        self.schedule_finger_table_update(ctx);
+        self.schedule_stabilization(ctx);
+        self.schedule_health_check(ctx);
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        println!("Node {} stopped", self.id);
+    }
 }
 
 impl Handler<JoinMessage> for Node {
+    type Result = ResponseActFuture<Self, Result<(), JoinError>>;
+
+    fn handle(&mut self, msg: JoinMessage, _: &mut Self::Context) -> Self::Result {
+        // Initialise the finger table through the bootstrap node; finger 0 is our successor
+        let bootstrap = msg.bootstrap.filter(|bootstrap| bootstrap.id != self.id);
+        let peers = self.peers.clone();
+        let own_id = self.id;
+        let starts: Vec<Identifier> = (0..self.fingers.len()).map(|i| self.fingers.get(i).start).collect();
+        Box::pin(
+            async move {
+                let Some(bootstrap) = bootstrap else {
+                    return Ok(Vec::new());
+                };
+
+                let mut fingers: Vec<NodeRef> = Vec::with_capacity(starts.len());
+                for start in starts {
+                    // A finger whose start falls before the previous finger's node shares that node
+                    if let Some(previous) = fingers.last() {
+                        if start.in_half_open_interval(own_id, previous.id) {
+                            fingers.push(previous.clone());
+                            continue;
+                        }
+                    }
+
+                    // Without our successor we can't join at all; later fingers are left to fix_fingers
+                    match peers.find_successor(&bootstrap, start, Vec::new()).await {
+                        Ok(Some(route)) => fingers.push(route.owner),
+                        Ok(None) if fingers.is_empty() => return Err(JoinError::NoSuccessor(bootstrap.id)),
+                        Err(e) if fingers.is_empty() => return Err(JoinError::Peer(bootstrap.id, e)),
+                        _ => break,
+                    }
+                }
+                Ok(fingers)
+            }
+            .into_actor(self)
+            .map(|fingers, node, ctx| {
+                let fingers = match fingers {
+                    Ok(fingers) => fingers,
+                    Err(e) => {
+                        println!("Node {} failed to join: {}", node.id, e);
+                        return Err(e);
+                    }
+                };
+                if let Some(successor) = fingers.first() {
+                    node.set_successors(vec![successor.clone()]);
+                }
+                for (i, finger) in fingers.into_iter().enumerate() {
+                    node.fingers.set(i, finger);
+                }
+                println!("Node {} joined with successor {}", node.id, node.successor().id);
+
+                // Take over our share of the successor's keys before it learns we are its predecessor
+                let successor = node.successor();
+                let alone = successor.id == node.id;
+                let new_node = node.node_ref();
+                let peers = node.peers.clone();
+                let target = successor.clone();
+                ctx.spawn(
+                    async move {
+                        if alone {
+                            return None;
+                        }
+                        Some(peers.migrate_keys(&target, new_node).await)
+                    }
+                    .into_actor(node)
+                    .map(move |result, node, ctx| {
+                        match result {
+                            Some(Ok(moved)) => println!("Node {} took over {} keys from {}", node.id, moved, successor.id),
+                            Some(Err(e)) => println!("Node {} failed to take over keys from {}: {}", node.id, successor.id, e),
+                            None => {}
+                        }
+                        // Let the successor know about us right away instead of waiting for the next tick
+                        ctx.notify(StabilizeMessage);
+                    }),
+                );
+                Ok(())
+            }),
+        )
+    }
+}
+
+// Handler for NotifyJoin message: Chord notify, the sender thinks it might be our predecessor
+impl Handler<NotifyJoin> for Node {
     type Result = ();
 
-    fn handle(&mut self, msg: JoinMessage, _: &mut Self::Context) {
-        let pool = self.db_pool.clone();
-        let node_id = msg.node_id;
-        let address = self.address.clone();
-        let port = self.port;
+    fn handle(&mut self, msg: NotifyJoin, ctx: &mut Self::Context) {
+        let candidate = msg.new_node;
+        if candidate.id == self.id {
+            return;
+        }
 
-        actix::spawn(async move {
-            // Step 1: Find the closest predecessor in the database
-            let closest_predecessor = sqlx::query!(
-                "SELECT id FROM nodes WHERE id < $1 ORDER BY id DESC LIMIT 1",
-                node_id as i32
-            )
-            .fetch_optional(&pool)
-            .await
-            .ok()
-            .flatten()
-            .map(|record| record.id);
-
-            // Step 2: Set the predecessor for the new node
-            let predecessor_id = closest_predecessor;
-            match sqlx::query!(
-                "INSERT INTO nodes (id, address, port, predecessor) VALUES ($1, $2, $3, $4)
-                ON CONFLICT (id) DO UPDATE SET predecessor = $4",
-                node_id as i32,
-                address,
-                port as i32,
-                predecessor_id
-            )
-            .execute(&pool)
-            .await
-            {
-                Ok(_) => println!("Node {} joined the DHT with predecessor {:?}", node_id, predecessor_id),
-                Err(e) => println!("Failed to insert or update node {} in database: {:?}", node_id, e),
-            }
+        let is_closer = match &self.predecessor {
+            Some(predecessor) => candidate.id.in_open_interval(predecessor.id, self.id),
+            None => true,
+        };
+        if !is_closer {
+            return;
+        }
 
-            // Step 3: Optional - Update the successor of the predecessor if needed
-            if let Some(pred_id) = predecessor_id {
-                let _ = sqlx::query!(
-                    "UPDATE nodes SET successor = $1 WHERE id = $2",
-                    node_id as i32,
-                    pred_id
-                )
-                .execute(&pool)
-                .await;
+        println!("Node {} notified by node {}, updating predecessor", self.id, candidate.id);
+        let node_id = self.id;
+        let update = self.registry.set_predecessor(node_id, Some(candidate.id));
+        actix::spawn(async move {
+            if let Err(e) = update.await {
+                println!("Failed to record predecessor of node {}: {}", node_id, e);
             }
         });
+        self.predecessor = Some(candidate);
+
+        // A lone node takes its first predecessor as its successor too, closing the ring
+        if self.successor().id == self.id {
+            self.adopt_successor(self.predecessor.clone().unwrap());
+        }
+
+        // Our range changed; if it grew over a failed predecessor's keys, our replicas of them take over
+        self.promote_replicas(ctx);
     }
 }
 
-// Handler for NotifyJoin message
-impl Handler<NotifyJoin> for Node {
-    type Result = ();
+// Handler for GetPredecessor message
+impl Handler<GetPredecessor> for Node {
+    type Result = MessageResult<GetPredecessor>;
+
+    fn handle(&mut self, _: GetPredecessor, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.predecessor.clone())
+    }
+}
 
-    fn handle(&mut self, msg: NotifyJoin, _: &mut Self::Context) {
-        println!("Node {} notified of new join for node {}", self.id, msg.new_node_id);
-        // Update successor or other relevant attributes as necessary
+// Handler for GetNodeRef message
+impl Handler<GetNodeRef> for Node {
+    type Result = MessageResult<GetNodeRef>;
+
+    fn handle(&mut self, _: GetNodeRef, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.node_ref())
+    }
+}
+
+// Handler for GetSuccessorList message
+impl Handler<GetSuccessorList> for Node {
+    type Result = MessageResult<GetSuccessorList>;
+
+    fn handle(&mut self, _: GetSuccessorList, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.successors.clone())
     }
 }
 
+// What stabilize learned from the first successor that answered
+struct StabilizeOutcome {
+    // Successors that did not answer and should be dropped
+    failed: Vec<Identifier>,
+    // The live successor, its predecessor and its successor list
+    reached: Option<(NodeRef, Option<NodeRef>, Vec<NodeRef>)>,
+}
 
+// Chord stabilize: ask the successor for its predecessor, adopt it if it sits between us, then notify the successor.
+// Successors that don't answer are skipped in favour of the next entry in the successor list.
 impl Handler<StabilizeMessage> for Node {
-    type Result = ();
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, _: StabilizeMessage, _: &mut Self::Context) -> Self::Result {
+        let candidates = self.successors.clone();
+        let peers = self.peers.clone();
+
+        Box::pin(
+            async move {
+                let mut failed = Vec::new();
+                for successor in candidates {
+                    let predecessor = match peers.get_predecessor(&successor).await {
+                        Ok(predecessor) => predecessor,
+                        Err(_) => {
+                            failed.push(successor.id);
+                            continue;
+                        }
+                    };
+                    let successor_list = peers.get_successor_list(&successor).await.unwrap_or_default();
+                    return StabilizeOutcome {
+                        failed,
+                        reached: Some((successor, predecessor, successor_list)),
+                    };
+                }
+                StabilizeOutcome { failed, reached: None }
+            }
+            .into_actor(self)
+            .map(|outcome, node, _| {
+                for failed in outcome.failed {
+                    println!("Node {}: successor {} failed, skipping it", node.id, failed);
+                    node.evict_peer(failed);
+                }
+
+                let candidate = match outcome.reached {
+                    Some((successor, predecessor, successor_list)) => {
+                        // Our list is the live successor followed by its own list
+                        let mut successors = vec![successor];
+                        successors.extend(successor_list);
+                        node.set_successors(successors);
+                        predecessor
+                    }
+                    // We are our own successor, so its predecessor is ours
+                    None => node.predecessor.clone(),
+                };
+                if let Some(candidate) = candidate {
+                    node.adopt_successor(candidate);
+                }
 
-    fn handle(&mut self, _: StabilizeMessage, ctx: &mut Self::Context) {
-        if let Some(predecessor_id) = self.predecessor {
-            println!("Node {} stabilizing with predecessor {}", self.id, predecessor_id);
-            if self.id == predecessor_id {
-                // This node has no predecessor; point to itself
-                self.predecessor = Some(self.id);
-            } else {
-                // Attempt to update the successor
-                let result = ctx.address().try_send(UpdateSuccessor { new_successor_id: self.id });
-                match result {
-                    Ok(_) => println!("Node {} updated its successor successfully.", self.id),
-                    Err(e) => println!("Node {} failed to update successor: {:?}", self.id, e),
+                let successor = node.successor();
+                if successor.id == node.id {
+                    return;
                 }
+                let peers = node.peers.clone();
+                let new_node = node.node_ref();
+                let node_id = node.id;
+                actix::spawn(async move {
+                    if let Err(e) = peers.notify(&successor, new_node).await {
+                        println!("Node {}: failed to notify successor {}: {}", node_id, successor.id, e);
+                    }
+                });
+            }),
+        )
+    }
+}
+
+// Handler for GetNodeState message
+impl Handler<GetNodeState> for Node {
+    type Result = ResponseFuture<serde_json::Value>;
+
+    fn handle(&mut self, _: GetNodeState, _: &mut Self::Context) -> Self::Result {
+        let mut state = serde_json::to_value(&*self).unwrap_or_default();
+        let keys = self.storage.count(self.id);
+        Box::pin(async move {
+            // Rows stored on this node: the keys it owns plus the replicas it holds for its predecessors
+            match keys.await {
+                Ok(keys) => {
+                    state["keys"] = (keys.owned + keys.replicas).into();
+                    state["owned_keys"] = keys.owned.into();
+                    state["replica_keys"] = keys.replicas.into();
+                }
+                Err(e) => println!("Failed to count keys of node {}: {}", state["id"], e),
             }
-        }
+            state
+        })
     }
 }
 
@@ -218,8 +877,8 @@ impl Handler<UpdateSuccessor> for Node {
     type Result = ();
 
     fn handle(&mut self, msg: UpdateSuccessor, _: &mut Self::Context) {
-        println!("Node {} updating successor to node {}", self.id, msg.new_successor_id);
         // Update successor information if the new successor is more appropriate
+        self.adopt_successor(msg.new_successor);
     }
 }
 
@@ -227,203 +886,178 @@ impl Handler<UpdateSuccessor> for Node {
 impl Handler<FixFingersMessage> for Node {
     type Result = ();
 
-    fn handle(&mut self, _: FixFingersMessage, _: &mut Self::Context) {
-        for i in 0..self.fingers.len() as i32 {
-            // Simple simulation of finger table update
-            self.fingers.insert(i, (self.id + 2_i32.pow(i as u32)) % 1024);
-            println!("Node {} fixing finger {}", self.id, i);
-        }
+    fn handle(&mut self, _: FixFingersMessage, ctx: &mut Self::Context) {
+        // One finger per tick, as in the Chord paper; the table is refreshed over m ticks
+        self.fix_next_finger(ctx);
     }
 }
 
 // Handler for LookupMessage
 impl Handler<LookupMessage> for Node {
-    type Result = Option<i32>;
+    type Result = ResponseFuture<Option<Route>>;
 
     fn handle(&mut self, msg: LookupMessage, _: &mut Self::Context) -> Self::Result {
-        let key_hash = hash_key(&msg.key.to_string());
-
-        // Check if this node is responsible for the key
-        if self.id == (key_hash % 1024) as i32 {
-            Some(self.id)
-        } else {
-            // Route lookup request to the closest preceding node
-            if let Some(next_node_id) = self.closest_preceding_node(key_hash as i32) {
-                println!("Node {} forwarding lookup for key {} to node {}", self.id, msg.key, next_node_id);
-                None // In a complete implementation, you'd forward the request
-            } else {
-                println!("Node {}: No closer node found for key {}", self.id, msg.key);
-                None
-            }
+        let key_id = self.config.space.key_id(&msg.key);
+        match msg.mode {
+            LookupMode::Iterative => self.iterative_find_successor(key_id),
+            LookupMode::Recursive => self.find_successor(key_id, Vec::new()),
         }
     }
 }
 
+// Handler for FindSuccessor
+impl Handler<FindSuccessor> for Node {
+    type Result = ResponseFuture<Option<Route>>;
 
-
-pub async fn insert_node(pool: &PgPool, node: &Node) -> Result<(), Error> {
-    sqlx::query!(
-        "INSERT INTO nodes (id, address, port, predecessor) VALUES ($1, $2, $3, $4)",
-        node.id as i32,
-        node.address,
-        node.port as i32,
-        node.predecessor as Option<i32>
-    )
-    .execute(pool)
-    .await?;
-    Ok(())
+    fn handle(&mut self, msg: FindSuccessor, _: &mut Self::Context) -> Self::Result {
+        self.find_successor(msg.id, msg.path)
+    }
 }
 
-pub async fn update_predecessor(pool: &PgPool, node_id: i32, predecessor: i32) -> Result<(), Error> {
-    sqlx::query!(
-        "UPDATE nodes SET predecessor = $1 WHERE id = $2",
-        predecessor as i32,
-        node_id as i32
-    )
-    .execute(pool)
-    .await?;
-    Ok(())
-}
+// Handler for NextHopMessage
+impl Handler<NextHopMessage> for Node {
+    type Result = MessageResult<NextHopMessage>;
 
-pub async fn insert_key_value(pool: &PgPool, key: i32, value: String, node_id: i32) -> Result<(), Error> {
-    sqlx::query!(
-        "INSERT INTO key_values (key, value, node_id) VALUES ($1, $2, $3)",
-        key as i32,
-        value,
-        node_id as i32
-    )
-    .execute(pool)
-    .await?;
-    Ok(())
+    fn handle(&mut self, msg: NextHopMessage, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.next_hop(msg.id))
+    }
 }
 
-impl KeyValue {
-    pub async fn insert(pool: &PgPool, key: i32, value: String, node_id: i32) -> Result<(), sqlx::Error> {
-        sqlx::query!(
-            "INSERT INTO key_values (key, value, node_id) VALUES ($1, $2, $3)",
-            key as i32,
-            value,
-            node_id as i32
-        )
-        .execute(pool)
-        .await?;
-        Ok(())
-    }
 
-    pub async fn get(pool: &PgPool, key: i32) -> Result<Option<String>, sqlx::Error> {
-        let result = sqlx::query!("SELECT value FROM key_values WHERE key = $1", key as i32)
-            .fetch_optional(pool)
-            .await?;
-        Ok(result.map(|r| r.value))
-    }
 
-    pub async fn delete(pool: &PgPool, key: i32) -> Result<(), sqlx::Error> {
-        sqlx::query!("DELETE FROM key_values WHERE key = $1", key as i32)
-            .execute(pool)
-            .await?;
-        Ok(())
-    }
-}
-
-#[derive(Message)]
-#[rtype(result = "Result<(), sqlx::Error>")]
+// Key requests from the HTTP API, handled by the key's owner as coordinator: it answers once
+// `consistency` copies (its own included) have acknowledged, and fails if they can't.
+// Writes answer with the key's versions after the write, reads with every concurrent version found.
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<Vec<Version>, QuorumError>")]
 pub struct InsertKeyValue {
-    pub key: i32,
+    pub key: Key,
     pub value: String,
+    // What the client had read of the key; the versions it covers are replaced, the rest kept as siblings
+    pub context: VectorClock,
+    pub consistency: Consistency,
 }
 
-#[derive(Message)]
-#[rtype(result = "Result<Option<String>, sqlx::Error>")]
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<Vec<Version>, QuorumError>")]
 pub struct GetKeyValue {
-    pub key: i32,
+    pub key: Key,
+    pub consistency: Consistency,
 }
 
-#[derive(Message)]
-#[rtype(result = "Result<(), sqlx::Error>")]
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<(), QuorumError>")]
 pub struct DeleteKeyValue {
-    pub key: i32,
+    pub key: Key,
+    pub consistency: Consistency,
+}
+
+// Sent by a key's owner to read a replica's copy for a quorum read
+#[derive(Message, Deserialize, Serialize)]
+#[rtype(result = "Result<Vec<Version>, StorageError>")]
+pub struct GetReplica {
+    pub key: Key,
 }
 
 impl Handler<InsertKeyValue> for Node {
-    type Result = Result<(), sqlx::Error>;
+    type Result = ResponseFuture<Result<Vec<Version>, QuorumError>>;
 
-    fn handle(&mut self, msg: InsertKeyValue, ctx: &mut Self::Context) -> Self::Result {
-        let pool = self.db_pool.clone();
+    fn handle(&mut self, msg: InsertKeyValue, _: &mut Self::Context) -> Self::Result {
+        let required = match msg.consistency.required(self.config.replication_factor) {
+            Ok(required) => required,
+            Err(e) => return Box::pin(async move { Err(e) }),
+        };
         let node_id = self.id;
-
-        // Clone msg.value so it can be used multiple times
-        let value_for_insert = msg.value.clone();
-        let value_for_successor = msg.value.clone();
-        let value_for_predecessor = msg.value.clone();
-
-        // Insert into the current node's database
-        actix::spawn(async move {
-            KeyValue::insert(&pool, msg.key, value_for_insert, node_id).await.unwrap();
-        });
-
-        // Replicate to successor and predecessor
-        if let Some(successor_id) = self.fingers.get(&0) {
-            let successor_addr = ctx.address().clone();
-            successor_addr.do_send(ReplicateData {
-                key: msg.key,
-                value: value_for_successor,
-            });
-        }
-
-        if let Some(predecessor_id) = self.predecessor {
-            let predecessor_addr = ctx.address().clone();
-            predecessor_addr.do_send(ReplicateData {
-                key: msg.key,
-                value: value_for_predecessor,
-            });
-        }
-
-        Ok(())
+        let (value, context) = (msg.value, msg.context);
+        let timestamp = self.clock.now();
+        let (merge_key, conflicts) = (msg.key.clone(), self.config.conflicts.clone());
+        self.coordinate_write(
+            msg.key,
+            required,
+            Box::new(move |current| {
+                let version = Version::coordinate(node_id, Some(value), context, timestamp, &current);
+                conflicts.merge(&merge_key, current, vec![version])
+            }),
+        )
     }
 }
 
-
 impl Handler<GetKeyValue> for Node {
-    type Result = ResponseFuture<Result<Option<String>, sqlx::Error>>;
+    type Result = ResponseFuture<Result<Vec<Version>, QuorumError>>;
 
     fn handle(&mut self, msg: GetKeyValue, _: &mut Self::Context) -> Self::Result {
-        let pool = self.db_pool.clone();
-        let key = msg.key;
+        let required = match msg.consistency.required(self.config.replication_factor) {
+            Ok(required) => required,
+            Err(e) => return Box::pin(async move { Err(e) }),
+        };
+        let node_id = self.id;
+        let get = self.storage.get(node_id, msg.key.clone());
+
+        // Only ask the replicas when the level needs more than our own copy; ask all of them
+        // so a replica that is down can be made up for by the next
+        let holders = if required > 1 { self.replica_holders() } else { Vec::new() };
+        let reads: Vec<_> = holders
+            .into_iter()
+            .map(|peer| {
+                let peers = self.peers.clone();
+                let key = msg.key.clone();
+                (peer.id, async move {
+                    let result = peers.get_replica(&peer, key.clone()).await;
+                    if let Err(e) = &result {
+                        println!("Node {}: failed to read replica of key {} on {}: {}", node_id, key, peer.id, e);
+                    }
+                    result
+                })
+            })
+            .collect();
+        let (key, conflicts) = (msg.key, self.config.conflicts.clone());
 
         Box::pin(async move {
-            match KeyValue::get(&pool, key).await {
-                Ok(value) => Ok(value),  // Return the Option<String> from KeyValue::get
-                Err(e) => Err(e),
-            }
+            let local = get.await?;
+            let replicas = gather(reads, required - 1, required).await?;
+            // A replica may hold a write the owner missed, e.g. right after a promotion; merge what everyone has
+            Ok(replicas.into_iter().fold(local, |merged, versions| conflicts.merge(&key, merged, versions)))
         })
     }
 }
 
 impl Handler<DeleteKeyValue> for Node {
-    type Result = Result<(), sqlx::Error>;
+    type Result = ResponseFuture<Result<(), QuorumError>>;
 
     fn handle(&mut self, msg: DeleteKeyValue, _: &mut Self::Context) -> Self::Result {
-        let pool = self.db_pool.clone();
-        let key = msg.key;
-
-        actix::spawn(async move {
-            KeyValue::delete(&pool, key).await.unwrap();
-        });
-        Ok(())
+        let required = match msg.consistency.required(self.config.replication_factor) {
+            Ok(required) => required,
+            Err(e) => return Box::pin(async move { Err(e) }),
+        };
+        let node_id = self.id;
+        let timestamp = self.clock.now();
+        let (merge_key, conflicts) = (msg.key.clone(), self.config.conflicts.clone());
+        // A tombstone written over every version we hold, replicated like any other write
+        let write = self.coordinate_write(
+            msg.key,
+            required,
+            Box::new(move |current| {
+                let tombstone = Version::coordinate(node_id, None, context_of(&current), timestamp, &current);
+                conflicts.merge(&merge_key, current, vec![tombstone])
+            }),
+        );
+        Box::pin(async move { write.await.map(|_| ()) })
     }
 }
 
-pub fn hash_key(key: &str) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    key.hash(&mut hasher);
-    hasher.finish()
+impl Handler<GetReplica> for Node {
+    type Result = ResponseFuture<Result<Vec<Version>, StorageError>>;
+
+    fn handle(&mut self, msg: GetReplica, _: &mut Self::Context) -> Self::Result {
+        self.storage.get(self.id, msg.key)
+    }
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct HealthCheck;
 
-#[derive(Message)]
+#[derive(Message, Serialize, Deserialize)]
 #[rtype(result = "()")]
 pub struct Heartbeat;
 
@@ -431,82 +1065,386 @@ impl Handler<Heartbeat> for Node {
     type Result = ();
 
     fn handle(&mut self, _: Heartbeat, _: &mut Self::Context) -> Self::Result {
-        // Respond to the heartbeat to indicate this node is alive
-        println!("Node {} received heartbeat", self.id);
+        // Answering the heartbeat is enough to show this node is alive
     }
 }
 
-impl Handler<HealthCheck> for Node {
-    type Result = ();
-
-    fn handle(&mut self, _: HealthCheck, ctx: &mut Self::Context) {
-        let node_id = self.id; // Copy self.id to a local variable
+// Ping a peer and report whether it answered within the RPC timeout
+async fn ping(peers: &PeerClient, peer: &NodeRef) -> bool {
+    peers.heartbeat(peer).await.is_ok()
+}
 
-        if let Some(successor_id) = self.fingers.get(&0) {
-            let successor_addr = ctx.address().clone();
-            actix::spawn(async move {
-                match successor_addr.send(Heartbeat).await {
-                    Ok(_) => println!("Node {}: Successor is alive", node_id),
-                    Err(_) => println!("Node {}: Successor failed health check", node_id),
+// check_predecessor plus a liveness check of the successor: failed peers are dropped and reported
+impl Handler<HealthCheck> for Node {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, _: HealthCheck, _: &mut Self::Context) -> Self::Result {
+        let peers = self.peers.clone();
+        let predecessor = self.predecessor.clone();
+        let successor = Some(self.successor()).filter(|s| s.id != self.id);
+
+        Box::pin(
+            async move {
+                let predecessor = match predecessor {
+                    Some(peer) => Some((peer.id, ping(&peers, &peer).await)),
+                    None => None,
+                };
+                let successor = match successor {
+                    Some(peer) => Some((peer.id, ping(&peers, &peer).await)),
+                    None => None,
+                };
+                (predecessor, successor)
+            }
+            .into_actor(self)
+            .map(|(predecessor, successor), node, ctx| {
+                if let Some((peer_id, alive)) = predecessor {
+                    node.record_health(peer_id, PeerRole::Predecessor, alive);
+                    // Only clear the predecessor if nobody replaced it while we were waiting
+                    if !alive && node.predecessor.as_ref().map(|p| p.id) == Some(peer_id) {
+                        println!("Node {}: predecessor {} failed, clearing it", node.id, peer_id);
+                        node.predecessor = None;
+                        let node_id = node.id;
+                        let update = node.registry.set_predecessor(node_id, None);
+                        actix::spawn(async move {
+                            if let Err(e) = update.await {
+                                println!("Failed to record predecessor of node {}: {}", node_id, e);
+                            }
+                        });
+                        // Left alone on the ring, we now own every key we hold a replica of
+                        node.promote_replicas(ctx);
+                    }
                 }
-            });
-        }
 
-        if let Some(predecessor_id) = self.predecessor {
-            let predecessor_addr = ctx.address().clone();
-            actix::spawn(async move {
-                match predecessor_addr.send(Heartbeat).await {
-                    Ok(_) => println!("Node {}: Predecessor is alive", node_id),
-                    Err(_) => println!("Node {}: Predecessor failed health check", node_id),
+                if let Some((peer_id, alive)) = successor {
+                    node.record_health(peer_id, PeerRole::Successor, alive);
+                    if !alive {
+                        // Fail over to the next entry of the successor list and tell it about us
+                        println!("Node {}: successor {} failed, failing over", node.id, peer_id);
+                        node.evict_peer(peer_id);
+                        ctx.notify(StabilizeMessage);
+                    }
                 }
-            });
-        }
+            }),
+        )
     }
 }
 
-
 #[derive(Message, Deserialize, Serialize)]
-#[rtype(result = "Result<(), sqlx::Error>")]
+#[rtype(result = "Result<(), StorageError>")]
 pub struct ReplicateData {
-    pub key: i32,
-    pub value: String,
+    pub key: Key,
+    pub versions: Vec<Version>,
+    // Which owner the copy belongs to and its rank among that owner's replicas
+    pub replica: Replica,
 }
 
 impl Handler<ReplicateData> for Node {
-    type Result = Result<(), sqlx::Error>;
+    type Result = ResponseFuture<Result<(), StorageError>>;
 
     fn handle(&mut self, msg: ReplicateData, _: &mut Self::Context) -> Self::Result {
-        let pool = self.db_pool.clone();
-        let node_id = self.id;
-
-        actix::spawn(async move {
-            KeyValue::insert(&pool, msg.key, msg.value, node_id).await.unwrap();
-        });
-        Ok(())
+        // Acknowledge only once the copy is stored; the owner counts this towards its write quorum
+        // Later writes we coordinate must be stamped after the ones we hold copies of
+        for version in &msg.versions {
+            self.clock.observe(version.timestamp);
+        }
+        let (key, versions, conflicts) = (msg.key.clone(), msg.versions, self.config.conflicts.clone());
+        // A key we own stays ours; the copy only adds its versions
+        let update = self.storage.replicate(
+            self.id,
+            msg.key,
+            msg.replica,
+            Box::new(move |current| conflicts.merge(&key, current, versions)),
+        );
+        Box::pin(async move { update.await.map(|_| ()) })
     }
 }
 
-#[derive(Message)]
-#[rtype(result = "Result<(), sqlx::Error>")]
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<(), StorageError>")]
 pub struct TransferData {
+    pub from: Identifier, // Node handing the keys over
     pub data: Vec<KeyValue>, // List of key-value pairs to transfer
 }
 
 impl Handler<TransferData> for Node {
-    type Result = Result<(), sqlx::Error>;
+    type Result = ResponseActFuture<Self, Result<(), StorageError>>;
 
     fn handle(&mut self, msg: TransferData, _: &mut Self::Context) -> Self::Result {
+        let owned = Replica::owned_by(self.id);
+        let taken: Vec<KeyValue> = msg.data.iter().filter(|kv| kv.replica == owned).cloned().collect();
+        for version in msg.data.iter().flat_map(|kv| &kv.versions) {
+            self.clock.observe(version.timestamp);
+        }
+        // Take ownership of the rows in one step so a key is never owned by both nodes or neither,
+        // then give the keys we now own their replicas
+        Box::pin(self.storage.transfer(msg.from, self.id, msg.data, self.config.conflicts.clone()).into_actor(self).map(
+            move |result, node, _| {
+                if result.is_ok() {
+                    node.replicate_rows(taken);
+                }
+                result
+            },
+        ))
+    }
+}
+
+// Result of a graceful leave
+#[derive(Serialize, Debug, Clone)]
+pub struct LeaveSummary {
+    pub node_id: Identifier,
+    pub successor: Option<NodeRef>,
+    pub keys_moved: usize,
+}
+
+// Why keys could not be handed from one node to another
+#[derive(Debug)]
+pub enum HandoffError {
+    Storage(StorageError),
+    // The receiving node did not take over the keys
+    Peer(Identifier, RpcError),
+    // The last node on the ring still holds this many keys, and there is nobody to hand them to
+    LastNode(usize),
+}
+
+impl std::fmt::Display for HandoffError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandoffError::Storage(e) => write!(f, "{}", e),
+            HandoffError::Peer(id, e) => write!(f, "node {}: {}", id, e),
+            HandoffError::LastNode(keys) => write!(f, "it is the last node on the ring and still holds {} keys", keys),
+        }
+    }
+}
+
+impl From<StorageError> for HandoffError {
+    fn from(e: StorageError) -> Self {
+        HandoffError::Storage(e)
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<LeaveSummary, HandoffError>")]
+pub struct LeaveMessage;
+
+// Sent by a joining node to its successor to claim the keys it now owns
+#[derive(Message, Serialize, Deserialize)]
+#[rtype(result = "Result<usize, HandoffError>")]
+pub struct MigrateKeys {
+    pub to: NodeRef,
+}
+
+// Sent by a leaving node to its predecessor and successor so they can link up around it
+#[derive(Message, Serialize, Deserialize, Clone)]
+#[rtype(result = "()")]
+pub struct NeighborLeaving {
+    pub leaving: Identifier,
+    pub predecessor: Option<NodeRef>,
+    pub successors: Vec<NodeRef>,
+}
+
+// Copy rows owned by `node_id` to its replica holders; the i-th holder's copies get rank i
+async fn push_replicas(peers: PeerClient, node_id: Identifier, holders: Vec<NodeRef>, rows: Vec<KeyValue>) {
+    for (peer, rank) in holders.iter().zip(1..=u8::MAX) {
+        let replica = Replica { owner: node_id, rank };
+        for kv in &rows {
+            if let Err(e) = peers.replicate(peer, kv.key.clone(), kv.versions.clone(), replica).await {
+                // Don't wait out a timeout per key on a holder that is down; stabilize will replace it
+                println!("Node {}: failed to replicate key {} to {}: {}", node_id, kv.key, peer.id, e);
+                break;
+            }
+        }
+    }
+}
+
+// Drop our copies of keys another node has taken over. With a store shared by both nodes
+// the receiver's transfer already removed them, so this finds nothing.
+async fn release(storage: &dyn Storage, node_id: Identifier, keys: &[Key]) -> Result<(), StorageError> {
+    for key in keys {
+        storage.delete(node_id, key.clone()).await?;
+    }
+    Ok(())
+}
+
+// Hand every key we own to `successor` and drop all our rows, replicas included; returns how many keys moved.
+// The successor already holds the first replica of each key, so it is the natural new owner;
+// the replicas we hold for others are restored by their owners' own holders.
+async fn hand_off(storage: &dyn Storage, peers: &PeerClient, node_id: Identifier, successor: &NodeRef) -> Result<usize, HandoffError> {
+    let rows = storage.scan(node_id, ALL_KEYS).await?;
+    let keys: Vec<Key> = rows.iter().map(|kv| kv.key.clone()).collect();
+    let data: Vec<KeyValue> = rows
+        .into_iter()
+        .filter(|kv| kv.replica.is_owner())
+        .map(|kv| KeyValue { replica: Replica::owned_by(successor.id), ..kv })
+        .collect();
+    let moved = data.len();
+    if moved > 0 {
+        peers.transfer(successor, node_id, data).await.map_err(|e| HandoffError::Peer(successor.id, e))?;
+    }
+    release(storage, node_id, &keys).await?;
+    Ok(moved)
+}
+
+// Graceful leave: hand the keys we own to the successor, drop our replicas, re-link the neighbours, then stop.
+// A leave that succeeds leaves no rows behind, so the node's registry entry can go with it.
+impl Handler<LeaveMessage> for Node {
+    type Result = ResponseActFuture<Self, Result<LeaveSummary, HandoffError>>;
+
+    fn handle(&mut self, _: LeaveMessage, _: &mut Self::Context) -> Self::Result {
+        let storage = self.storage.clone();
         let node_id = self.id;
+        let peers = self.peers.clone();
+        let successor = Some(self.successor()).filter(|s| s.id != self.id);
+        let predecessor = self.predecessor.clone().filter(|p| p.id != self.id);
+        let notice = NeighborLeaving {
+            leaving: self.id,
+            predecessor: self.predecessor.clone(),
+            successors: self.successors.clone(),
+        };
+
+        Box::pin(
+            async move {
+                // The last node on the ring has nobody to hand its keys to, so it stays until they are deleted
+                let Some(heir) = &successor else {
+                    let keys = storage.scan(node_id, ALL_KEYS).await?.len();
+                    if keys > 0 {
+                        return Err(HandoffError::LastNode(keys));
+                    }
+                    return Ok(LeaveSummary { node_id, successor, keys_moved: 0 });
+                };
+                let mut keys_moved = hand_off(&*storage, &peers, node_id, heir).await?;
+
+                for neighbor in predecessor.iter().chain(successor.iter()) {
+                    if let Err(e) = peers.neighbor_leaving(neighbor, notice.clone()).await {
+                        println!("Node {}: failed to tell {} it is leaving: {}", node_id, neighbor.id, e);
+                    }
+                }
+                // Writes and replica copies that reached us while the keys were moving; the neighbours have
+                // re-linked by now, so no more should come. We are off the ring either way, so a failure here
+                // only leaves rows behind, which keep the registry entry from being removed.
+                match hand_off(&*storage, &peers, node_id, heir).await {
+                    Ok(moved) => keys_moved += moved,
+                    Err(e) => println!("Node {}: failed to hand off keys written during its leave: {}", node_id, e),
+                }
+                Ok(LeaveSummary { node_id, successor, keys_moved })
+            }
+            .into_actor(self)
+            .map(|result, node, ctx| {
+                if let Ok(summary) = &result {
+                    println!("Node {} left the ring, moved {} keys", node.id, summary.keys_moved);
+                    node.nodes.lock().unwrap().remove(&node.id);
+                    broadcast(
+                        &node.clients,
+                        &NodeEvent::NodeLeft {
+                            node_id: node.id,
+                            successor_id: summary.successor.as_ref().map(|s| s.id),
+                            keys_moved: summary.keys_moved,
+                        },
+                    );
+                    ctx.stop();
+                }
+                result
+            }),
+        )
+    }
+}
+
+// A node joined just before us: every key we own outside (new node, self] now belongs to it,
+// and our copy stays on as its first replica
+impl Handler<MigrateKeys> for Node {
+    type Result = ResponseActFuture<Self, Result<usize, HandoffError>>;
+
+    fn handle(&mut self, msg: MigrateKeys, _: &mut Self::Context) -> Self::Result {
+        let storage = self.storage.clone();
+        let node_id = self.id;
+        let space = self.config.space;
+        let peers = self.peers.clone();
+        let target = msg.to;
+        let to = target.id;
+        let keep_replicas = self.config.replication_factor > 1;
+        let conflicts = self.config.conflicts.clone();
+
+        Box::pin(
+            async move {
+                let data: Vec<KeyValue> = storage
+                    .scan(node_id, ALL_KEYS)
+                    .await?
+                    .into_iter()
+                    .filter(|kv| kv.replica.is_owner() && !space.key_id(&kv.key).in_half_open_interval(to, node_id))
+                    .map(|kv| KeyValue { replica: Replica::owned_by(to), ..kv })
+                    .collect();
+                if data.is_empty() {
+                    return Ok(0);
+                }
+                peers
+                    .transfer(&target, node_id, data.clone())
+                    .await
+                    .map_err(|e| HandoffError::Peer(to, e))?;
+                if keep_replicas {
+                    // The new owner's replication will say the same; tagging now keeps our copy from looking owned meanwhile
+                    let replica = Replica { owner: to, rank: 1 };
+                    for kv in &data {
+                        let (key, versions, conflicts) = (kv.key.clone(), kv.versions.clone(), conflicts.clone());
+                        let merge = Box::new(move |current| conflicts.merge(&key, current, versions));
+                        storage.update(node_id, kv.key.clone(), replica, merge).await?;
+                    }
+                } else {
+                    let keys: Vec<Key> = data.iter().map(|kv| kv.key.clone()).collect();
+                    release(&*storage, node_id, &keys).await?;
+                }
+                Ok(data.len())
+            }
+            .into_actor(self)
+            .map(move |result, node, _| {
+                if let Ok(keys_moved) = result {
+                    println!("Node {} migrated {} keys to {}", node.id, keys_moved, to);
+                    broadcast(
+                        &node.clients,
+                        &NodeEvent::KeysMigrated { from: node.id, to, keys_moved },
+                    );
+                }
+                result
+            }),
+        )
+    }
+}
 
-        for kv in msg.data {
-            let pool_clone = self.db_pool.clone(); // Clone pool for each async task
+impl Handler<NeighborLeaving> for Node {
+    type Result = ();
 
+    fn handle(&mut self, msg: NeighborLeaving, _: &mut Self::Context) {
+        // Our predecessor is leaving: its predecessor becomes ours
+        if self.predecessor.as_ref().map(|p| p.id) == Some(msg.leaving) {
+            let predecessor = msg.predecessor.filter(|p| p.id != self.id);
+            println!(
+                "Node {}: predecessor {} left, new predecessor {:?}",
+                self.id,
+                msg.leaving,
+                predecessor.as_ref().map(|p| p.id)
+            );
+            let node_id = self.id;
+            let update = self.registry.set_predecessor(node_id, predecessor.as_ref().map(|p| p.id));
             actix::spawn(async move {
-                KeyValue::insert(&pool_clone, kv.key, kv.value, node_id).await.unwrap();
+                if let Err(e) = update.await {
+                    println!("Failed to record predecessor of node {}: {}", node_id, e);
+                }
             });
+            self.predecessor = predecessor;
         }
 
-        Ok(())
+        // Fingers pointing at the leaving node now point at the node that takes over its keys
+        let heir = msg.successors.iter().find(|s| s.id != msg.leaving).cloned();
+        let remaining = self.successors.iter().filter(|s| s.id != msg.leaving).cloned();
+        let successors: Vec<NodeRef> = match self.successors.iter().position(|s| s.id == msg.leaving) {
+            // Our successor is leaving: its successor list takes its place at the head of ours
+            Some(0) => msg.successors.into_iter().chain(remaining).collect(),
+            // A later entry is leaving: our closer successors stay, and its successors top the list up
+            Some(_) => remaining.chain(msg.successors).collect(),
+            None => return,
+        };
+        println!("Node {}: successor {} left", self.id, msg.leaving);
+        self.set_successors(successors);
+        let replacement = heir.filter(|heir| heir.id != self.id).unwrap_or_else(|| self.successor());
+        self.fingers.replace(msg.leaving, &replacement);
     }
 }
 
@@ -517,7 +1455,271 @@ pub struct FingerTableUpdate;
 impl Handler<FingerTableUpdate> for Node {
     type Result = ();
 
-    fn handle(&mut self, _: FingerTableUpdate, _: &mut Self::Context) -> Self::Result {
-        self.update_finger_table();
+    fn handle(&mut self, _: FingerTableUpdate, ctx: &mut Self::Context) -> Self::Result {
+        self.fix_next_finger(ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::clock::sleep;
+    use super::super::version::live;
+    use crate::storage::{MemoryRegistry, MemoryStorage};
+
+    // Nodes in one process talking over the loopback transport, with timers long enough that
+    // the tests drive stabilization themselves
+    struct Ring {
+        space: IdentifierSpace,
+        nodes: NodesMap,
+        storage: Arc<MemoryStorage>,
+        registry: Arc<MemoryRegistry>,
+        clients: Clients,
+    }
+
+    impl Ring {
+        fn new() -> Self {
+            Ring {
+                space: IdentifierSpace::new(6),
+                nodes: NodesMap::default(),
+                storage: Arc::new(MemoryStorage::new()),
+                registry: Arc::new(MemoryRegistry::new()),
+                clients: Clients::default(),
+            }
+        }
+
+        fn config(&self) -> NodeConfig {
+            let idle = Duration::from_secs(3600);
+            NodeConfig {
+                space: self.space,
+                stabilize_interval: idle,
+                fix_fingers_interval: idle,
+                check_predecessor_interval: idle,
+                successor_list_len: 2,
+                replication_factor: 2,
+                rpc_timeout: Duration::from_millis(500),
+                transport: TransportKind::Loopback,
+                ..NodeConfig::default()
+            }
+        }
+
+        fn start(&self, id: u64) -> Addr<Node> {
+            let node = Node::new(
+                Identifier::new(id),
+                "127.0.0.1".to_string(),
+                5000 + id as i32,
+                self.config(),
+                self.registry.clone(),
+                self.storage.clone(),
+                self.nodes.clone(),
+                self.clients.clone(),
+            )
+            .unwrap()
+            .start();
+            self.nodes.lock().unwrap().insert(Identifier::new(id), node.clone());
+            node
+        }
+
+        fn node(&self, id: u64) -> Addr<Node> {
+            self.nodes.lock().unwrap()[&Identifier::new(id)].clone()
+        }
+
+        fn ids(&self) -> Vec<u64> {
+            let mut ids: Vec<u64> = self.nodes.lock().unwrap().keys().map(|id| id.value()).collect();
+            ids.sort();
+            ids
+        }
+
+        // Start `ids` and join each through the first, then let the ring settle
+        async fn build(ids: &[u64]) -> Ring {
+            let ring = Ring::new();
+            ring.start(ids[0]);
+            for &id in &ids[1..] {
+                ring.join(id, ids[0]).await;
+            }
+            ring.settle().await;
+            ring
+        }
+
+        async fn join(&self, id: u64, bootstrap: u64) {
+            let bootstrap = self.node(bootstrap).send(GetNodeRef).await.unwrap();
+            let joined = self.start(id).send(JoinMessage { node_id: Identifier::new(id), bootstrap: Some(bootstrap) });
+            joined.await.unwrap().unwrap();
+            // Key migration and the first stabilize run once the join has answered
+            sleep(Duration::from_millis(50)).await;
+        }
+
+        // A few rounds of stabilize and fix_fingers on every node
+        async fn settle(&self) {
+            for _ in 0..3 {
+                for id in self.ids() {
+                    self.node(id).send(StabilizeMessage).await.unwrap();
+                    for _ in 0..self.space.bits() {
+                        self.node(id).send(FixFingersMessage).await.unwrap();
+                    }
+                }
+                sleep(Duration::from_millis(50)).await;
+            }
+        }
+
+        // The first node at or after the key's id, going round the ring
+        fn expected_owner(&self, key: &Key) -> u64 {
+            let key_id = self.space.key_id(key).value();
+            let ids = self.ids();
+            ids.iter().copied().find(|&id| id >= key_id).unwrap_or(ids[0])
+        }
+
+        async fn insert(&self, key: &Key, value: &str) {
+            let insert = InsertKeyValue {
+                key: key.clone(),
+                value: value.to_string(),
+                context: VectorClock::default(),
+                consistency: Consistency::One,
+            };
+            self.node(self.expected_owner(key)).send(insert).await.unwrap().unwrap();
+        }
+
+        async fn read(&self, owner: u64, key: &Key) -> Vec<String> {
+            let get = GetKeyValue { key: key.clone(), consistency: Consistency::One };
+            let versions = self.node(owner).send(get).await.unwrap().unwrap();
+            live(&versions).into_iter().map(str::to_string).collect()
+        }
+
+        // Keys the node holds, split into the ones it owns and its replicas
+        async fn rows(&self, id: u64) -> (Vec<Key>, Vec<Key>) {
+            let rows = self.storage.scan(Identifier::new(id), ALL_KEYS).await.unwrap();
+            let (owned, replicas): (Vec<KeyValue>, Vec<KeyValue>) = rows.into_iter().partition(|kv| kv.replica.is_owner());
+            (owned.into_iter().map(|kv| kv.key).collect(), replicas.into_iter().map(|kv| kv.key).collect())
+        }
+
+        async fn successors(&self, id: u64) -> Vec<u64> {
+            let successors = self.node(id).send(GetSuccessorList).await.unwrap();
+            successors.iter().map(|s| s.id.value()).collect()
+        }
+
+        async fn predecessor(&self, id: u64) -> Option<u64> {
+            self.node(id).send(GetPredecessor).await.unwrap().map(|p| p.id.value())
+        }
+    }
+
+    fn keys() -> Vec<Key> {
+        (0..24).map(|i| Key::new(format!("key-{}", i).into_bytes())).collect()
+    }
+
+    #[actix::test]
+    async fn stabilized_ring_links_every_node_to_its_neighbours() {
+        let ring = Ring::build(&[10, 30, 50]).await;
+        assert_eq!(ring.successors(10).await, vec![30, 50]);
+        assert_eq!(ring.successors(30).await, vec![50, 10]);
+        assert_eq!(ring.successors(50).await, vec![10, 30]);
+        assert_eq!(ring.predecessor(10).await, Some(50));
+        assert_eq!(ring.predecessor(30).await, Some(10));
+        assert_eq!(ring.predecessor(50).await, Some(30));
+    }
+
+    #[actix::test]
+    async fn lookups_from_any_node_find_the_keys_successor() {
+        let ring = Ring::build(&[10, 30, 50]).await;
+        for key in keys() {
+            let owner = ring.expected_owner(&key);
+            for id in ring.ids() {
+                for mode in [LookupMode::Iterative, LookupMode::Recursive] {
+                    let route = ring.node(id).send(LookupMessage { key: key.clone(), mode }).await.unwrap().unwrap();
+                    assert_eq!(route.owner.id.value(), owner, "{:?} lookup of {} from {}", mode, key, id);
+                }
+            }
+        }
+    }
+
+    #[actix::test]
+    async fn joining_node_takes_over_its_share_of_the_keys() {
+        let ring = Ring::build(&[10]).await;
+        for key in keys() {
+            ring.insert(&key, "v").await;
+        }
+        ring.join(40, 10).await;
+        ring.settle().await;
+
+        let (owned_by_40, _) = ring.rows(40).await;
+        let (owned_by_10, replicas_on_10) = ring.rows(10).await;
+        assert!(!owned_by_40.is_empty() && !owned_by_10.is_empty(), "the keys should be split between the nodes");
+        assert_eq!(owned_by_40.len() + owned_by_10.len(), keys().len());
+        for key in keys() {
+            let owner = ring.expected_owner(&key);
+            let owned = if owner == 40 { &owned_by_40 } else { &owned_by_10 };
+            assert!(owned.contains(&key), "{} should be owned by {}", key, owner);
+            assert_eq!(ring.read(owner, &key).await, vec!["v"]);
+        }
+        // The old owner keeps a copy of what it handed over, as the new owner's first replica
+        for key in &owned_by_40 {
+            assert!(replicas_on_10.contains(key));
+        }
+    }
+
+    #[actix::test]
+    async fn leaving_node_hands_its_keys_to_its_successor() {
+        let ring = Ring::build(&[10, 30, 50]).await;
+        for key in keys() {
+            ring.insert(&key, "v").await;
+        }
+        sleep(Duration::from_millis(50)).await;
+        let (owned_by_30, _) = ring.rows(30).await;
+
+        let summary = ring.node(30).send(LeaveMessage).await.unwrap().unwrap();
+        assert_eq!(summary.successor.map(|s| s.id.value()), Some(50));
+        assert_eq!(summary.keys_moved, owned_by_30.len());
+        assert_eq!(ring.ids(), vec![10, 50]);
+        assert_eq!(ring.rows(30).await, (Vec::new(), Vec::new()));
+        ring.settle().await;
+
+        assert_eq!(ring.successors(10).await, vec![50]);
+        assert_eq!(ring.predecessor(50).await, Some(10));
+        let (owned_by_50, _) = ring.rows(50).await;
+        for key in keys() {
+            let owner = ring.expected_owner(&key);
+            if owner == 50 {
+                assert!(owned_by_50.contains(&key), "{} should have moved to 50", key);
+            }
+            assert_eq!(ring.read(owner, &key).await, vec!["v"]);
+        }
+    }
+
+    #[actix::test]
+    async fn last_node_cannot_leave_with_keys() {
+        let ring = Ring::build(&[10]).await;
+        ring.insert(&keys()[0], "v").await;
+        match ring.node(10).send(LeaveMessage).await.unwrap() {
+            Err(HandoffError::LastNode(1)) => {}
+            other => panic!("expected the leave to be refused, got {:?}", other.map(|s| s.keys_moved)),
+        }
+        assert_eq!(ring.ids(), vec![10]);
+    }
+
+    #[actix::test]
+    async fn ring_recovers_from_a_failed_successor() {
+        let ring = Ring::build(&[10, 30, 50]).await;
+        for key in keys() {
+            ring.insert(&key, "v").await;
+        }
+        sleep(Duration::from_millis(50)).await;
+        let (owned_by_30, _) = ring.rows(30).await;
+        assert!(!owned_by_30.is_empty());
+
+        // Node 30 goes silent: the loopback transport can no longer reach it
+        ring.nodes.lock().unwrap().remove(&Identifier::new(30));
+        for id in ring.ids() {
+            ring.node(id).send(HealthCheck).await.unwrap();
+        }
+        sleep(Duration::from_millis(50)).await;
+        ring.settle().await;
+
+        assert_eq!(ring.successors(10).await, vec![50]);
+        assert_eq!(ring.predecessor(50).await, Some(10));
+        // 50 held the first replica of 30's keys and now owns them
+        let (owned_by_50, _) = ring.rows(50).await;
+        for key in &owned_by_30 {
+            assert!(owned_by_50.contains(key), "{} should have been promoted on 50", key);
+            assert_eq!(ring.read(50, key).await, vec!["v"]);
+        }
     }
 }
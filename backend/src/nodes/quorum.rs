@@ -0,0 +1,295 @@
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use tokio::sync::mpsc;
+use super::identifier::Identifier;
+use super::rpc::RpcError;
+use crate::storage::StorageError;
+
+// How many of a key's N copies must answer before a read (R) or write (W) succeeds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Consistency {
+    // The owner's copy alone
+    #[default]
+    One,
+    // A majority of the N copies
+    Quorum,
+    // Every copy
+    All,
+    // An explicit R or W
+    Count(usize),
+}
+
+impl Consistency {
+    // Copies that must answer when the key has `replicas` of them
+    pub fn required(self, replicas: usize) -> Result<usize, QuorumError> {
+        let required = match self {
+            Consistency::One => 1,
+            Consistency::Quorum => replicas / 2 + 1,
+            Consistency::All => replicas,
+            Consistency::Count(count) => count,
+        };
+        if required == 0 || required > replicas {
+            return Err(QuorumError::Unsatisfiable { consistency: self, replicas });
+        }
+        Ok(required)
+    }
+}
+
+impl fmt::Display for Consistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Consistency::One => write!(f, "one"),
+            Consistency::Quorum => write!(f, "quorum"),
+            Consistency::All => write!(f, "all"),
+            Consistency::Count(count) => write!(f, "{}", count),
+        }
+    }
+}
+
+// "one", "quorum", "all" in any case, or a number of copies
+impl FromStr for Consistency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "one" => Ok(Consistency::One),
+            "quorum" => Ok(Consistency::Quorum),
+            "all" => Ok(Consistency::All),
+            other => other
+                .parse()
+                .map(Consistency::Count)
+                .map_err(|_| format!("expected one, quorum, all or a number of copies, got {:?}", s)),
+        }
+    }
+}
+
+impl Serialize for Consistency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Consistency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug)]
+pub enum QuorumError {
+    // The level asks for no copies, or more than the key has
+    Unsatisfiable { consistency: Consistency, replicas: usize },
+    // Fewer copies answered than the level requires; `failures` says which replicas let us down and how
+    NotMet { required: usize, acks: usize, failures: Vec<(Identifier, RpcError)> },
+    // The owner's own copy could not be read or written
+    Storage(StorageError),
+    // The key's owner runs in another process and could not be asked, or failed as one of the above
+    Remote(Identifier, RpcError),
+}
+
+// A QuorumError on its way back from an owner in another process, keeping what kind of failure it was
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum QuorumFailure {
+    Unsatisfiable(String),
+    NotMet(String),
+    Storage(String),
+}
+
+impl fmt::Display for QuorumFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuorumFailure::Unsatisfiable(message) | QuorumFailure::NotMet(message) | QuorumFailure::Storage(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl QuorumError {
+    // What the owner reports back when it coordinated a request for another process
+    pub fn failure(&self) -> QuorumFailure {
+        let message = self.to_string();
+        match self {
+            QuorumError::Unsatisfiable { .. } => QuorumFailure::Unsatisfiable(message),
+            QuorumError::Storage(_) => QuorumFailure::Storage(message),
+            QuorumError::NotMet { .. } | QuorumError::Remote(..) => QuorumFailure::NotMet(message),
+        }
+    }
+}
+
+impl fmt::Display for QuorumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuorumError::Unsatisfiable { consistency, replicas } => {
+                write!(f, "consistency {} cannot be met with {} copies per key", consistency, replicas)
+            }
+            QuorumError::NotMet { required, acks, failures } => {
+                write!(f, "quorum not met: {} of {} required copies answered", acks, required)?;
+                for (node_id, e) in failures {
+                    write!(f, "; node {}: {}", node_id, e)?;
+                }
+                Ok(())
+            }
+            QuorumError::Storage(e) => write!(f, "{}", e),
+            QuorumError::Remote(owner, e) => write!(f, "node {}: {}", owner, e),
+        }
+    }
+}
+
+impl From<StorageError> for QuorumError {
+    fn from(e: StorageError) -> Self {
+        QuorumError::Storage(e)
+    }
+}
+
+// Run the calls to the replicas, each tagged with the replica's id, and wait until `needed` of them succeed;
+// the owner's own copy is the caller's to count, so `required` is the total reported when the quorum fails.
+// Calls still running once the quorum is reached carry on in the background, so every replica is still
+// brought up to date. Gives up as soon as too many replicas have failed for the rest to make up the quorum.
+pub async fn gather<T, F>(calls: Vec<(Identifier, F)>, needed: usize, required: usize) -> Result<Vec<T>, QuorumError>
+where
+    T: 'static,
+    F: Future<Output = Result<T, RpcError>> + 'static,
+{
+    let total = calls.len();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    for (node_id, call) in calls {
+        let tx = tx.clone();
+        actix::spawn(async move {
+            let _ = tx.send((node_id, call.await));
+        });
+    }
+    drop(tx);
+
+    let mut answers = Vec::with_capacity(needed);
+    let mut failures = Vec::new();
+    while answers.len() < needed {
+        if total - failures.len() < needed {
+            return Err(QuorumError::NotMet { required, acks: answers.len() + 1, failures });
+        }
+        match rx.recv().await {
+            Some((_, Ok(answer))) => answers.push(answer),
+            Some((node_id, Err(e))) => failures.push((node_id, e)),
+            None => return Err(QuorumError::NotMet { required, acks: answers.len() + 1, failures }),
+        }
+    }
+    Ok(answers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future;
+    use std::pin::Pin;
+    use std::time::Duration;
+    use actix::clock::{sleep, timeout};
+
+    type Call = Pin<Box<dyn Future<Output = Result<u64, RpcError>>>>;
+
+    fn id(value: u64) -> Identifier {
+        Identifier::new(value)
+    }
+
+    fn answers_after(ms: u64, value: u64) -> Call {
+        Box::pin(async move {
+            sleep(Duration::from_millis(ms)).await;
+            Ok(value)
+        })
+    }
+
+    fn fails_after(ms: u64) -> Call {
+        Box::pin(async move {
+            sleep(Duration::from_millis(ms)).await;
+            Err(RpcError::Timeout)
+        })
+    }
+
+    fn never() -> Call {
+        Box::pin(future::pending())
+    }
+
+    #[test]
+    fn levels_scale_with_the_copies() {
+        for (replicas, quorum) in [(1, 1), (2, 2), (3, 2), (4, 3), (5, 3)] {
+            assert_eq!(Consistency::One.required(replicas).unwrap(), 1);
+            assert_eq!(Consistency::Quorum.required(replicas).unwrap(), quorum);
+            assert_eq!(Consistency::All.required(replicas).unwrap(), replicas);
+            assert_eq!(Consistency::Count(replicas).required(replicas).unwrap(), replicas);
+        }
+    }
+
+    #[test]
+    fn counts_outside_the_copies_are_unsatisfiable() {
+        for consistency in [Consistency::Count(0), Consistency::Count(4)] {
+            match consistency.required(3) {
+                Err(QuorumError::Unsatisfiable { consistency: got, replicas: 3 }) => assert_eq!(got, consistency),
+                other => panic!("{} of 3 was accepted: {:?}", consistency, other),
+            }
+        }
+    }
+
+    #[test]
+    fn levels_parse_by_name_or_count() {
+        assert_eq!("QUORUM".parse::<Consistency>().unwrap(), Consistency::Quorum);
+        assert_eq!("2".parse::<Consistency>().unwrap(), Consistency::Count(2));
+        assert!("most".parse::<Consistency>().is_err());
+    }
+
+    #[actix::test]
+    async fn returns_once_enough_replicas_answer() {
+        // The slow replica would hold the quorum up for a minute if gather waited for it
+        let calls = vec![(id(1), answers_after(0, 1)), (id(2), answers_after(60_000, 2)), (id(3), answers_after(5, 3))];
+        let answers = timeout(Duration::from_secs(5), gather(calls, 2, 3)).await.unwrap().unwrap();
+        assert_eq!(answers, vec![1, 3]);
+    }
+
+    #[actix::test]
+    async fn fails_as_soon_as_the_quorum_is_out_of_reach() {
+        // Two of three failed, so the replica that never answers cannot make a quorum of two
+        let calls = vec![(id(1), fails_after(0)), (id(2), never()), (id(3), fails_after(5))];
+        match timeout(Duration::from_secs(5), gather(calls, 2, 3)).await.unwrap() {
+            Err(QuorumError::NotMet { required: 3, acks: 1, failures }) => {
+                let mut failed: Vec<Identifier> = failures.iter().map(|(node_id, _)| *node_id).collect();
+                failed.sort();
+                assert_eq!(failed, vec![id(1), id(3)]);
+            }
+            other => panic!("expected the quorum to fail, got {:?}", other),
+        }
+    }
+
+    #[actix::test]
+    async fn counts_the_owner_and_the_replicas_that_did_answer() {
+        let calls = vec![(id(1), answers_after(0, 1)), (id(2), fails_after(5)), (id(3), fails_after(10))];
+        match gather(calls, 2, 3).await {
+            Err(QuorumError::NotMet { required: 3, acks: 2, failures }) => assert_eq!(failures.len(), 2),
+            other => panic!("expected the quorum to fail, got {:?}", other),
+        }
+    }
+
+    #[actix::test]
+    async fn needing_more_replicas_than_were_asked_fails_at_once() {
+        let calls = vec![(id(1), never())];
+        match timeout(Duration::from_secs(5), gather(calls, 2, 3)).await.unwrap() {
+            Err(QuorumError::NotMet { acks: 1, failures, .. }) => assert!(failures.is_empty()),
+            other => panic!("expected the quorum to fail, got {:?}", other),
+        }
+    }
+
+    #[actix::test]
+    async fn no_replicas_needed_succeeds_without_waiting() {
+        let calls = vec![(id(1), never())];
+        assert!(gather(calls, 0, 1).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn failures_keep_their_kind_across_processes() {
+        let unsatisfiable = Consistency::Count(5).required(3).unwrap_err();
+        assert!(matches!(unsatisfiable.failure(), QuorumFailure::Unsatisfiable(_)));
+        let not_met = QuorumError::NotMet { required: 2, acks: 1, failures: vec![(id(4), RpcError::Timeout)] };
+        assert_eq!(not_met.to_string(), "quorum not met: 1 of 2 required copies answered; node 4: timed out");
+        assert!(matches!(not_met.failure(), QuorumFailure::NotMet(_)));
+    }
+}
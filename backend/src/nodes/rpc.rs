@@ -0,0 +1,153 @@
+use actix::prelude::*;
+use std::fmt;
+use tokio::net::{TcpListener, TcpStream};
+use super::identifier::Identifier;
+use super::node_actor::{
+    DeleteKeyValue, FindSuccessor, GetKeyValue, GetNodeRef, GetReplica, GetPredecessor, GetSuccessorList, Heartbeat,
+    InsertKeyValue, MigrateKeys, NeighborLeaving, NextHop, NextHopMessage, Node, NodeRef, NotifyJoin, ReplicateData, Route,
+    TransferData,
+};
+use super::quorum::QuorumFailure;
+use super::version::Version;
+use super::wire::{self, WireError};
+
+// Chord messages that travel between nodes; see the wire module for their encoding
+pub enum RpcRequest {
+    FindSuccessor(FindSuccessor),
+    NextHop(NextHopMessage),
+    Notify(NotifyJoin),
+    GetPredecessor(GetPredecessor),
+    GetSuccessorList(GetSuccessorList),
+    Heartbeat(Heartbeat),
+    ReplicateData(ReplicateData),
+    GetReplica(GetReplica),
+    TransferData(TransferData),
+    MigrateKeys(MigrateKeys),
+    NeighborLeaving(NeighborLeaving),
+    // Ask a node at a known address for its id
+    Identify(GetNodeRef),
+    // Key requests from the HTTP API, for the key's owner to coordinate
+    InsertKeyValue(InsertKeyValue),
+    GetKeyValue(GetKeyValue),
+    DeleteKeyValue(DeleteKeyValue),
+}
+
+#[derive(Debug)]
+pub enum RpcResponse {
+    Route(Option<Route>),
+    NextHop(NextHop),
+    Predecessor(Option<NodeRef>),
+    Successors(Vec<NodeRef>),
+    KeysMoved(usize),
+    NodeRef(NodeRef),
+    // A replica's versions of a key
+    Versions(Vec<Version>),
+    Ack,
+    // The peer received the request but could not carry it out
+    Error(String),
+    // The peer coordinated a key request and could not get its consistency level
+    QuorumFailed(QuorumFailure),
+}
+
+#[derive(Debug)]
+pub enum RpcError {
+    Wire(WireError),
+    Timeout,
+    // No route to the peer: it is not in this process (loopback) or not listening
+    Unreachable(Identifier),
+    Remote(String),
+    Quorum(QuorumFailure),
+    UnexpectedResponse,
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Wire(e) => write!(f, "{}", e),
+            RpcError::Timeout => write!(f, "timed out"),
+            RpcError::Unreachable(id) => write!(f, "node {} is unreachable", id),
+            RpcError::Remote(message) => write!(f, "remote error: {}", message),
+            RpcError::Quorum(failure) => write!(f, "{}", failure),
+            RpcError::UnexpectedResponse => write!(f, "unexpected response"),
+        }
+    }
+}
+
+impl From<WireError> for RpcError {
+    fn from(e: WireError) -> Self {
+        RpcError::Wire(e)
+    }
+}
+
+// Hand a request to the local node actor and wrap its answer
+pub async fn dispatch(node: &Addr<Node>, request: RpcRequest) -> RpcResponse {
+    let response = match request {
+        RpcRequest::FindSuccessor(msg) => node.send(msg).await.map(RpcResponse::Route),
+        RpcRequest::NextHop(msg) => node.send(msg).await.map(RpcResponse::NextHop),
+        RpcRequest::Notify(msg) => node.send(msg).await.map(|()| RpcResponse::Ack),
+        RpcRequest::GetPredecessor(msg) => node.send(msg).await.map(RpcResponse::Predecessor),
+        RpcRequest::GetSuccessorList(msg) => node.send(msg).await.map(RpcResponse::Successors),
+        RpcRequest::Heartbeat(msg) => node.send(msg).await.map(|()| RpcResponse::Ack),
+        RpcRequest::ReplicateData(msg) => node.send(msg).await.map(ack_or_error),
+        RpcRequest::GetReplica(msg) => node.send(msg).await.map(|result| match result {
+            Ok(versions) => RpcResponse::Versions(versions),
+            Err(e) => RpcResponse::Error(e.to_string()),
+        }),
+        RpcRequest::TransferData(msg) => node.send(msg).await.map(ack_or_error),
+        RpcRequest::MigrateKeys(msg) => node.send(msg).await.map(|result| match result {
+            Ok(keys_moved) => RpcResponse::KeysMoved(keys_moved),
+            Err(e) => RpcResponse::Error(e.to_string()),
+        }),
+        RpcRequest::NeighborLeaving(msg) => node.send(msg).await.map(|()| RpcResponse::Ack),
+        RpcRequest::Identify(msg) => node.send(msg).await.map(RpcResponse::NodeRef),
+        RpcRequest::InsertKeyValue(msg) => node.send(msg).await.map(|result| match result {
+            Ok(versions) => RpcResponse::Versions(versions),
+            Err(e) => RpcResponse::QuorumFailed(e.failure()),
+        }),
+        RpcRequest::GetKeyValue(msg) => node.send(msg).await.map(|result| match result {
+            Ok(versions) => RpcResponse::Versions(versions),
+            Err(e) => RpcResponse::QuorumFailed(e.failure()),
+        }),
+        RpcRequest::DeleteKeyValue(msg) => node.send(msg).await.map(|result| match result {
+            Ok(()) => RpcResponse::Ack,
+            Err(e) => RpcResponse::QuorumFailed(e.failure()),
+        }),
+    };
+    response.unwrap_or_else(|e| RpcResponse::Error(e.to_string()))
+}
+
+fn ack_or_error<E: fmt::Display>(result: Result<(), E>) -> RpcResponse {
+    match result {
+        Ok(()) => RpcResponse::Ack,
+        Err(e) => RpcResponse::Error(e.to_string()),
+    }
+}
+
+// Accept peer connections and answer their requests until the listener fails
+pub async fn serve(node: Addr<Node>, listener: TcpListener) {
+    loop {
+        let (stream, peer_addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                println!("RPC listener failed: {:?}", e);
+                return;
+            }
+        };
+        let node = node.clone();
+        actix::spawn(async move {
+            if let Err(e) = handle_connection(node, stream).await {
+                println!("RPC connection from {} failed: {}", peer_addr, e);
+            }
+        });
+    }
+}
+
+// Each connection starts with the version handshake, then carries any number of request/response pairs
+async fn handle_connection(node: Addr<Node>, mut stream: TcpStream) -> Result<(), WireError> {
+    wire::server_handshake(&mut stream).await?;
+    while let Some(request) = wire::read_request(&mut stream).await? {
+        let response = dispatch(&node, request).await;
+        wire::write_response(&mut stream, &response).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,218 @@
+use actix::prelude::*;
+use actix::clock::timeout;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use super::identifier::Identifier;
+use super::key::Key;
+use super::node_actor::{
+    DeleteKeyValue, FindSuccessor, GetKeyValue, GetNodeRef, GetPredecessor, GetReplica, GetSuccessorList,
+    Heartbeat, InsertKeyValue, MigrateKeys, NeighborLeaving, NextHop, NextHopMessage, NodeRef, NodesMap, NotifyJoin,
+    ReplicateData, Route, TransferData,
+};
+use super::rpc::{dispatch, RpcError, RpcRequest, RpcResponse};
+use super::version::Version;
+use super::wire::{self, WireError};
+use crate::storage::{KeyValue, Replica};
+
+// How a node reaches its peers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    // Peers are actors in this process, found through the nodes map
+    Loopback,
+    // Peers are contacted over TCP at their advertised address and port
+    #[default]
+    Tcp,
+}
+
+// Delivers one request to a peer and waits up to `timeout_after` for its answer
+pub trait Transport: fmt::Debug + Send + Sync {
+    fn call(&self, peer: NodeRef, request: RpcRequest, timeout_after: Duration) -> ResponseFuture<Result<RpcResponse, RpcError>>;
+}
+
+// Peers are `Node` actors in this process, looked up by id in the nodes map
+#[derive(Debug, Clone)]
+pub struct ActorTransport {
+    nodes: NodesMap,
+}
+
+impl ActorTransport {
+    pub fn new(nodes: NodesMap) -> Self {
+        ActorTransport { nodes }
+    }
+}
+
+impl Transport for ActorTransport {
+    fn call(&self, peer: NodeRef, request: RpcRequest, timeout_after: Duration) -> ResponseFuture<Result<RpcResponse, RpcError>> {
+        let node = self.nodes.lock().unwrap().get(&peer.id).cloned();
+        Box::pin(async move {
+            let node = node.ok_or(RpcError::Unreachable(peer.id))?;
+            timeout(timeout_after, dispatch(&node, request)).await.map_err(|_| RpcError::Timeout)
+        })
+    }
+}
+
+// Peers are reached over TCP at their advertised address, one connection per request
+#[derive(Debug, Clone, Default)]
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn call(&self, peer: NodeRef, request: RpcRequest, timeout_after: Duration) -> ResponseFuture<Result<RpcResponse, RpcError>> {
+        Box::pin(async move {
+            timeout(timeout_after, tcp_call(&peer, &request)).await.map_err(|_| RpcError::Timeout)?
+        })
+    }
+}
+
+async fn tcp_call(peer: &NodeRef, request: &RpcRequest) -> Result<RpcResponse, RpcError> {
+    let port = u16::try_from(peer.port).map_err(|_| RpcError::Unreachable(peer.id))?;
+    let mut stream = TcpStream::connect((peer.address.as_str(), port)).await.map_err(WireError::from)?;
+    wire::client_handshake(&mut stream).await?;
+    wire::write_request(&mut stream, request).await?;
+    wire::read_response(&mut stream).await?.ok_or(RpcError::Unreachable(peer.id))
+}
+
+// Typed Chord calls on top of whichever transport the node uses
+#[derive(Debug, Clone)]
+pub struct PeerClient {
+    transport: Arc<dyn Transport>,
+    // How long to wait for an answer before treating the peer as failed
+    timeout: Duration,
+    // The same for requests that move a batch of rows, which may take far longer than a ping
+    transfer_timeout: Duration,
+}
+
+impl PeerClient {
+    pub fn new(transport: Arc<dyn Transport>, timeout: Duration, transfer_timeout: Duration) -> Self {
+        PeerClient { transport, timeout, transfer_timeout }
+    }
+
+    // Transport for the configured kind
+    pub fn for_kind(kind: TransportKind, nodes: NodesMap, timeout: Duration, transfer_timeout: Duration) -> Self {
+        let transport: Arc<dyn Transport> = match kind {
+            TransportKind::Loopback => Arc::new(ActorTransport::new(nodes)),
+            TransportKind::Tcp => Arc::new(TcpTransport),
+        };
+        PeerClient::new(transport, timeout, transfer_timeout)
+    }
+
+    // Send one request to `peer`; an error reply from the peer becomes an RpcError
+    pub async fn call(&self, peer: &NodeRef, request: RpcRequest) -> Result<RpcResponse, RpcError> {
+        // A migration answers only once the keys have moved, so it waits as long as a transfer
+        let timeout_after = match request {
+            RpcRequest::TransferData(_) | RpcRequest::MigrateKeys(_) => self.transfer_timeout,
+            _ => self.timeout,
+        };
+        match self.transport.call(peer.clone(), request, timeout_after).await? {
+            RpcResponse::Error(message) => Err(RpcError::Remote(message)),
+            RpcResponse::QuorumFailed(failure) => Err(RpcError::Quorum(failure)),
+            response => Ok(response),
+        }
+    }
+
+    pub async fn find_successor(&self, peer: &NodeRef, id: Identifier, path: Vec<Identifier>) -> Result<Option<Route>, RpcError> {
+        match self.call(peer, RpcRequest::FindSuccessor(FindSuccessor { id, path })).await? {
+            RpcResponse::Route(route) => Ok(route),
+            _ => Err(RpcError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn next_hop(&self, peer: &NodeRef, id: Identifier) -> Result<NextHop, RpcError> {
+        match self.call(peer, RpcRequest::NextHop(NextHopMessage { id })).await? {
+            RpcResponse::NextHop(hop) => Ok(hop),
+            _ => Err(RpcError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn notify(&self, peer: &NodeRef, new_node: NodeRef) -> Result<(), RpcError> {
+        expect_ack(self.call(peer, RpcRequest::Notify(NotifyJoin { new_node })).await?)
+    }
+
+    pub async fn get_predecessor(&self, peer: &NodeRef) -> Result<Option<NodeRef>, RpcError> {
+        match self.call(peer, RpcRequest::GetPredecessor(GetPredecessor)).await? {
+            RpcResponse::Predecessor(predecessor) => Ok(predecessor),
+            _ => Err(RpcError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn get_successor_list(&self, peer: &NodeRef) -> Result<Vec<NodeRef>, RpcError> {
+        match self.call(peer, RpcRequest::GetSuccessorList(GetSuccessorList)).await? {
+            RpcResponse::Successors(successors) => Ok(successors),
+            _ => Err(RpcError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn heartbeat(&self, peer: &NodeRef) -> Result<(), RpcError> {
+        expect_ack(self.call(peer, RpcRequest::Heartbeat(Heartbeat)).await?)
+    }
+
+    pub async fn replicate(&self, peer: &NodeRef, key: Key, versions: Vec<Version>, replica: Replica) -> Result<(), RpcError> {
+        expect_ack(self.call(peer, RpcRequest::ReplicateData(ReplicateData { key, versions, replica })).await?)
+    }
+
+    pub async fn get_replica(&self, peer: &NodeRef, key: Key) -> Result<Vec<Version>, RpcError> {
+        match self.call(peer, RpcRequest::GetReplica(GetReplica { key })).await? {
+            RpcResponse::Versions(versions) => Ok(versions),
+            _ => Err(RpcError::UnexpectedResponse),
+        }
+    }
+
+    // Rows go over in as many requests as it takes to keep each under the frame limit. The receiver takes
+    // over each batch atomically and merges what it already holds, so resending after a failure is harmless.
+    pub async fn transfer(&self, peer: &NodeRef, from: Identifier, data: Vec<KeyValue>) -> Result<(), RpcError> {
+        for batch in wire::batches(data)? {
+            expect_ack(self.call(peer, RpcRequest::TransferData(TransferData { from, data: batch })).await?)?;
+        }
+        Ok(())
+    }
+
+    pub async fn migrate_keys(&self, peer: &NodeRef, to: NodeRef) -> Result<usize, RpcError> {
+        match self.call(peer, RpcRequest::MigrateKeys(MigrateKeys { to })).await? {
+            RpcResponse::KeysMoved(keys_moved) => Ok(keys_moved),
+            _ => Err(RpcError::UnexpectedResponse),
+        }
+    }
+
+    // Learn the id of the node listening at `address:port`, e.g. a bootstrap node given on the command line.
+    // Only transports that route by address can do this.
+    pub async fn identify(&self, address: &str, port: i32) -> Result<NodeRef, RpcError> {
+        let unknown = NodeRef { id: Identifier::new(0), address: address.to_string(), port };
+        match self.call(&unknown, RpcRequest::Identify(GetNodeRef)).await? {
+            RpcResponse::NodeRef(node) => Ok(node),
+            _ => Err(RpcError::UnexpectedResponse),
+        }
+    }
+
+    // Have the key's owner coordinate a write; answers with the key's versions after it
+    pub async fn insert(&self, owner: &NodeRef, msg: InsertKeyValue) -> Result<Vec<Version>, RpcError> {
+        match self.call(owner, RpcRequest::InsertKeyValue(msg)).await? {
+            RpcResponse::Versions(versions) => Ok(versions),
+            _ => Err(RpcError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn get(&self, owner: &NodeRef, msg: GetKeyValue) -> Result<Vec<Version>, RpcError> {
+        match self.call(owner, RpcRequest::GetKeyValue(msg)).await? {
+            RpcResponse::Versions(versions) => Ok(versions),
+            _ => Err(RpcError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn delete(&self, owner: &NodeRef, msg: DeleteKeyValue) -> Result<(), RpcError> {
+        expect_ack(self.call(owner, RpcRequest::DeleteKeyValue(msg)).await?)
+    }
+
+    pub async fn neighbor_leaving(&self, peer: &NodeRef, notice: NeighborLeaving) -> Result<(), RpcError> {
+        expect_ack(self.call(peer, RpcRequest::NeighborLeaving(notice)).await?)
+    }
+}
+
+fn expect_ack(response: RpcResponse) -> Result<(), RpcError> {
+    match response {
+        RpcResponse::Ack => Ok(()),
+        _ => Err(RpcError::UnexpectedResponse),
+    }
+}
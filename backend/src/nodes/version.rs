@@ -0,0 +1,393 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use super::hlc::Timestamp;
+use super::identifier::Identifier;
+use super::key::Key;
+
+// How many writes each coordinating node has made to a key, as far as some client or replica has seen
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct VectorClock(BTreeMap<Identifier, u64>);
+
+impl VectorClock {
+    pub fn get(&self, node: Identifier) -> u64 {
+        self.0.get(&node).copied().unwrap_or(0)
+    }
+
+    // Whether the write `dot` is part of this history
+    pub fn covers(&self, dot: Dot) -> bool {
+        self.get(dot.node) >= dot.counter
+    }
+
+    pub fn add(&mut self, dot: Dot) {
+        let counter = self.0.entry(dot.node).or_default();
+        *counter = (*counter).max(dot.counter);
+    }
+
+    // Take in everything `other` has seen
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (&node, &counter) in &other.0 {
+            self.add(Dot { node, counter });
+        }
+    }
+}
+
+// The context token clients get back from reads and hand to writes: "node:counter" pairs joined by commas
+impl fmt::Display for VectorClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (node, counter)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}:{}", node, counter)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for VectorClock {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut clock = VectorClock::default();
+        for entry in s.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let dot = entry
+                .split_once(':')
+                .and_then(|(node, counter)| Some(Dot { node: Identifier::new(node.parse().ok()?), counter: counter.parse().ok()? }))
+                .ok_or_else(|| format!("expected node:counter pairs separated by commas, got {:?}", s))?;
+            clock.add(dot);
+        }
+        Ok(clock)
+    }
+}
+
+// One write: the node that coordinated it and that node's running count of writes to the key
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Dot {
+    pub node: Identifier,
+    pub counter: u64,
+}
+
+// A value as written, with what the writer had already seen. Versions none of whose writers saw
+// each other are concurrent and kept side by side as siblings until a client writes over them all.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    // None for a delete: the tombstone is ordered like any write, so copies of the value that
+    // arrive late or sit on a lagging replica can't bring it back. Tombstones are never purged, since
+    // nothing tracks whether every copy has seen them: a deleted key keeps its row, on every copy, for good.
+    pub value: Option<String>,
+    pub dot: Dot,
+    // The context the client wrote with
+    pub context: VectorClock,
+    // When the coordinator took the write; decides between versions in last-writer-wins namespaces
+    pub timestamp: Timestamp,
+}
+
+impl Version {
+    // A write coordinated by `node` on top of `context`. Its counter is past any `node` has used for
+    // the key, so it never collides with a version already stored or seen by the client, and its
+    // timestamp is past the stored versions', so it wins over them under last-writer-wins.
+    pub fn coordinate(
+        node: Identifier,
+        value: Option<String>,
+        context: VectorClock,
+        timestamp: Timestamp,
+        current: &[Version],
+    ) -> Self {
+        let counter = current
+            .iter()
+            .flat_map(|version| [version.context.get(node), if version.dot.node == node { version.dot.counter } else { 0 }])
+            .chain([context.get(node)])
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let timestamp = current
+            .iter()
+            .map(|version| version.timestamp.successor(node))
+            .fold(timestamp, Timestamp::max);
+        Version { value, dot: Dot { node, counter }, context, timestamp }
+    }
+
+    // Whether this write was made by someone who had seen `other`, and so replaces it.
+    // Values stored before versioning carry counter 0: every write covers them, but they cover nothing.
+    pub fn supersedes(&self, other: &Version) -> bool {
+        self.dot != other.dot && self.dot.counter > 0 && self.context.covers(other.dot)
+    }
+}
+
+// The values a reader sees: tombstones hide the key, so a key whose versions are all deletes reads as missing
+pub fn live(versions: &[Version]) -> Vec<&str> {
+    versions.iter().filter_map(|version| version.value.as_deref()).collect()
+}
+
+// Merge two sets of siblings: the same write seen twice is kept once, and any version a write
+// in either set was made on top of is dropped. Ordered by dot so every replica agrees.
+pub fn reconcile(current: Vec<Version>, incoming: Vec<Version>) -> Vec<Version> {
+    let mut all: Vec<Version> = Vec::with_capacity(current.len() + incoming.len());
+    for version in current.into_iter().chain(incoming) {
+        if !all.iter().any(|kept| kept.dot == version.dot) {
+            all.push(version);
+        }
+    }
+    let mut siblings: Vec<Version> = all
+        .iter()
+        .filter(|version| !all.iter().any(|other| other.supersedes(version)))
+        .cloned()
+        .collect();
+    siblings.sort_by_key(|version| version.dot);
+    siblings
+}
+
+// Everything a reader of these siblings has seen; writing with it replaces all of them
+pub fn context_of(versions: &[Version]) -> VectorClock {
+    let mut clock = VectorClock::default();
+    for version in versions {
+        clock.merge(&version.context);
+        clock.add(version.dot);
+    }
+    clock
+}
+
+// Keep only the newest version, so a key never goes back to an older value whatever order copies arrive in
+pub fn last_writer_wins(current: Vec<Version>, incoming: Vec<Version>) -> Vec<Version> {
+    current.into_iter().chain(incoming).max_by_key(|version| version.timestamp).into_iter().collect()
+}
+
+// How concurrent writes to a key are settled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Resolution {
+    // Keep them all for the client to resolve
+    #[default]
+    Siblings,
+    // The write with the latest timestamp replaces the others
+    LastWriterWins,
+}
+
+// Which resolution each key namespace uses; see Key::namespace
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConflictPolicy {
+    // Namespaces resolved by last-writer-wins; every other key keeps siblings
+    pub last_writer_wins: Vec<String>,
+}
+
+impl ConflictPolicy {
+    pub fn resolution(&self, key: &Key) -> Resolution {
+        match key.namespace() {
+            Some(namespace) if self.last_writer_wins.iter().any(|lww| lww.as_bytes() == namespace) => Resolution::LastWriterWins,
+            _ => Resolution::Siblings,
+        }
+    }
+
+    // Combine two sets of a key's versions the way its namespace asks
+    pub fn merge(&self, key: &Key, current: Vec<Version>, incoming: Vec<Version>) -> Vec<Version> {
+        match self.resolution(key) {
+            Resolution::Siblings => reconcile(current, incoming),
+            Resolution::LastWriterWins => last_writer_wins(current, incoming),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::slice;
+
+    fn node(id: u64) -> Identifier {
+        Identifier::new(id)
+    }
+
+    fn at(wall_ms: u64, node: Identifier) -> Timestamp {
+        Timestamp { wall_ms, logical: 0, node }
+    }
+
+    fn clock(token: &str) -> VectorClock {
+        token.parse().unwrap()
+    }
+
+    // A write by `by` on top of `context` over the `current` siblings
+    fn write(by: u64, value: &str, context: &str, current: &[Version]) -> Version {
+        Version::coordinate(node(by), Some(value.to_string()), clock(context), at(1_000, node(by)), current)
+    }
+
+    // A value stored before versioning
+    fn legacy(value: &str, owner: u64) -> Version {
+        Version {
+            value: Some(value.to_string()),
+            dot: Dot { node: node(owner), counter: 0 },
+            context: VectorClock::default(),
+            timestamp: at(0, node(owner)),
+        }
+    }
+
+    // A delete by `by` of everything in `current`, coordinated at `wall_ms`
+    fn delete(by: u64, wall_ms: u64, current: &[Version]) -> Version {
+        Version::coordinate(node(by), None, context_of(current), at(wall_ms, node(by)), current)
+    }
+
+    #[test]
+    fn coordinate_counts_past_every_write_seen() {
+        let first = write(1, "a", "", &[]);
+        assert_eq!(first.dot, Dot { node: node(1), counter: 1 });
+
+        // Past our own stored write, whatever the client sent
+        let second = write(1, "b", "", slice::from_ref(&first));
+        assert_eq!(second.dot.counter, 2);
+
+        // Past a counter only the client's context has seen
+        assert_eq!(write(1, "c", "1:5", slice::from_ref(&second)).dot.counter, 6);
+
+        // Past what a stored version's writer had seen of us
+        let other = write(2, "d", "1:4", &[]);
+        assert_eq!(write(1, "e", "", slice::from_ref(&other)).dot.counter, 5);
+
+        // Other nodes' counters don't move ours
+        assert_eq!(write(3, "f", "1:9,2:7", &[other]).dot.counter, 1);
+    }
+
+    #[test]
+    fn coordinate_stamps_after_the_stored_versions() {
+        let stored = Version { timestamp: at(5_000, node(2)), ..write(2, "a", "", &[]) };
+        let next = write(1, "b", "", slice::from_ref(&stored));
+        assert!(next.timestamp > stored.timestamp);
+        assert_eq!(next.timestamp.node, node(1));
+    }
+
+    #[test]
+    fn only_writes_made_on_top_of_a_version_supersede_it() {
+        let a = write(1, "a", "", &[]);
+        let b = write(2, "b", "1:1", slice::from_ref(&a));
+        let c = write(3, "c", "", slice::from_ref(&a));
+        assert!(b.supersedes(&a));
+        assert!(!a.supersedes(&b));
+        assert!(!c.supersedes(&a));
+        assert!(!a.supersedes(&a));
+    }
+
+    #[test]
+    fn context_tokens_round_trip() {
+        let token = "3:2,7:1,12:40";
+        assert_eq!(clock(token).to_string(), token);
+        assert_eq!(clock(&clock(token).to_string()), clock(token));
+
+        // Pairs are ordered by node, and repeats keep the higher counter
+        assert_eq!(clock(" 7:1, 3:2 ,3:1,").to_string(), "3:2,7:1");
+        assert_eq!(clock("").to_string(), "");
+
+        assert!("3".parse::<VectorClock>().is_err());
+        assert!("3:x".parse::<VectorClock>().is_err());
+        assert!("-1:2".parse::<VectorClock>().is_err());
+    }
+
+    #[test]
+    fn context_of_covers_every_sibling_and_what_it_saw() {
+        let a = write(1, "a", "4:2", &[]);
+        let b = write(2, "b", "", &[]);
+        let context = context_of(&[a.clone(), b.clone()]);
+        assert!(context.covers(a.dot));
+        assert!(context.covers(b.dot));
+        assert_eq!(context.get(node(4)), 2);
+    }
+
+    #[test]
+    fn blind_writes_are_kept_as_siblings() {
+        let a = write(1, "a", "", &[]);
+        let b = write(2, "b", "", slice::from_ref(&a));
+        let siblings = reconcile(vec![b.clone()], vec![a.clone()]);
+        assert_eq!(siblings, vec![a.clone(), b.clone()]);
+
+        // The same write arriving twice is kept once
+        assert_eq!(reconcile(siblings.clone(), vec![a, b]), siblings);
+    }
+
+    #[test]
+    fn a_write_with_the_read_context_replaces_all_siblings() {
+        let a = write(1, "a", "", &[]);
+        let b = write(2, "b", "", &[]);
+        let siblings = reconcile(vec![a], vec![b]);
+        let resolved = write(3, "c", &context_of(&siblings).to_string(), &siblings);
+        assert_eq!(reconcile(siblings.clone(), vec![resolved.clone()]), vec![resolved.clone()]);
+
+        // Whichever side the resolving write arrives from
+        assert_eq!(reconcile(vec![resolved.clone()], siblings), vec![resolved]);
+    }
+
+    #[test]
+    fn reconcile_does_not_depend_on_arrival_order() {
+        let a = write(1, "a", "", &[]);
+        let b = write(2, "b", "1:1", slice::from_ref(&a));
+        let c = write(3, "c", "", &[]);
+        let forward = reconcile(reconcile(vec![a.clone()], vec![b.clone()]), vec![c.clone()]);
+        let backward = reconcile(reconcile(vec![c], vec![b]), vec![a]);
+        assert_eq!(forward, backward);
+        assert_eq!(forward.len(), 2);
+    }
+
+    #[test]
+    fn legacy_values_give_way_to_any_write() {
+        let old = legacy("old", 1);
+        let blind = write(2, "new", "", slice::from_ref(&old));
+        assert!(blind.supersedes(&old));
+        assert_eq!(reconcile(vec![old.clone()], vec![blind.clone()]), vec![blind]);
+
+        // But a legacy value replaces nothing, not even another legacy value
+        let other = legacy("other", 2);
+        assert!(!old.supersedes(&other));
+        assert_eq!(reconcile(vec![old.clone()], vec![other.clone()]), vec![old, other]);
+    }
+
+    #[test]
+    fn a_delete_keeps_late_copies_from_coming_back() {
+        let a = write(1, "a", "", &[]);
+        let b = write(2, "b", "", &[]);
+        let siblings = reconcile(vec![a.clone()], vec![b.clone()]);
+        let tombstone = delete(1, 1_000, &siblings);
+        let deleted = reconcile(siblings, vec![tombstone.clone()]);
+        assert_eq!(deleted, vec![tombstone.clone()]);
+        assert!(live(&deleted).is_empty());
+
+        // A replica that missed the delete hands back its old copies
+        assert_eq!(reconcile(deleted.clone(), vec![a, b]), vec![tombstone.clone()]);
+
+        // A write the delete hadn't seen survives it
+        let concurrent = write(3, "c", "", &[]);
+        let merged = reconcile(deleted.clone(), vec![concurrent.clone()]);
+        assert_eq!(live(&merged), vec!["c"]);
+
+        // And a write with the context of the deleted key replaces the tombstone
+        let again = write(2, "again", &context_of(&deleted).to_string(), &deleted);
+        assert_eq!(reconcile(deleted, vec![again.clone()]), vec![again]);
+    }
+
+    #[test]
+    fn last_writer_wins_orders_deletes_like_writes() {
+        let old = write(1, "old", "", &[]);
+        let tombstone = delete(2, 5_000, slice::from_ref(&old));
+        assert_eq!(last_writer_wins(vec![tombstone.clone()], vec![old.clone()]), vec![tombstone.clone()]);
+
+        let newer = write(1, "new", "", slice::from_ref(&tombstone));
+        assert!(newer.timestamp > tombstone.timestamp);
+        assert_eq!(last_writer_wins(vec![newer.clone()], vec![tombstone]), vec![newer]);
+    }
+
+    #[test]
+    fn merge_order_never_changes_the_last_writer() {
+        let versions = [
+            Version { timestamp: at(3_000, node(1)), ..write(1, "a", "", &[]) },
+            Version { timestamp: at(3_000, node(2)), ..write(2, "b", "", &[]) },
+            Version { timestamp: at(1_000, node(3)), ..write(3, "c", "", &[]) },
+            delete(4, 2_000, &[]),
+        ];
+        let expected = vec![versions[1].clone()];
+        let orders = [[0, 1, 2, 3], [3, 2, 1, 0], [1, 3, 0, 2], [2, 0, 3, 1], [3, 1, 2, 0]];
+        for order in orders {
+            let merged = order.iter().fold(Vec::new(), |merged, &i| last_writer_wins(merged, vec![versions[i].clone()]));
+            assert_eq!(merged, expected, "merged in order {:?}", order);
+
+            // Merging two halves merged on different replicas gives the same winner
+            let left = last_writer_wins(vec![versions[order[0]].clone()], vec![versions[order[1]].clone()]);
+            let right = last_writer_wins(vec![versions[order[2]].clone()], vec![versions[order[3]].clone()]);
+            assert_eq!(last_writer_wins(right, left), expected);
+        }
+    }
+}
@@ -0,0 +1,473 @@
+// Binary wire protocol between nodes.
+//
+// Every frame is a 4-byte big-endian length, a 1-byte message type and a postcard-encoded body;
+// the length covers the type byte and the body. A connection opens with a Hello frame carrying
+// the protocol version, and a node speaking a different version answers VersionMismatch and
+// hangs up, so mixed-version rings fail loudly instead of misreading each other's messages.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use super::rpc::{RpcRequest, RpcResponse};
+
+// Bump whenever a message or its encoding changes
+pub const PROTOCOL_VERSION: u16 = 10;
+
+// Sent first in every Hello so stray connections are told apart from peers
+const MAGIC: [u8; 4] = *b"CHRD";
+
+// Frames larger than this are rejected instead of allocated
+pub const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+// Room left in a batched request's frame for the type byte and the fields around the batch
+const BATCH_OVERHEAD: usize = 64;
+
+// Message type tags
+mod tag {
+    pub const HELLO: u8 = 0x01;
+    pub const HELLO_ACK: u8 = 0x02;
+    pub const VERSION_MISMATCH: u8 = 0x03;
+
+    pub const FIND_SUCCESSOR: u8 = 0x10;
+    pub const NEXT_HOP: u8 = 0x11;
+    pub const NOTIFY: u8 = 0x12;
+    pub const GET_PREDECESSOR: u8 = 0x13;
+    pub const GET_SUCCESSOR_LIST: u8 = 0x14;
+    pub const HEARTBEAT: u8 = 0x15;
+    pub const REPLICATE_DATA: u8 = 0x16;
+    pub const TRANSFER_DATA: u8 = 0x17;
+    pub const MIGRATE_KEYS: u8 = 0x18;
+    pub const NEIGHBOR_LEAVING: u8 = 0x19;
+    pub const IDENTIFY: u8 = 0x1a;
+    pub const GET_REPLICA: u8 = 0x1c;
+    pub const INSERT_KEY_VALUE: u8 = 0x1d;
+    pub const GET_KEY_VALUE: u8 = 0x1e;
+    pub const DELETE_KEY_VALUE: u8 = 0x1f;
+
+    pub const ROUTE: u8 = 0x40;
+    pub const NEXT_HOP_REPLY: u8 = 0x41;
+    pub const PREDECESSOR: u8 = 0x42;
+    pub const SUCCESSORS: u8 = 0x43;
+    pub const KEYS_MOVED: u8 = 0x44;
+    pub const ACK: u8 = 0x45;
+    pub const ERROR: u8 = 0x46;
+    pub const NODE_REF: u8 = 0x47;
+    pub const VERSIONS: u8 = 0x48;
+    pub const QUORUM_FAILED: u8 = 0x49;
+}
+
+#[derive(Debug)]
+pub enum WireError {
+    Io(io::Error),
+    // A frame body could not be encoded or decoded
+    Codec(postcard::Error),
+    FrameTooLarge(usize),
+    UnknownTag(u8),
+    // A valid frame that doesn't belong at this point of the conversation
+    UnexpectedFrame(u8),
+    // The other end did not open with a DHT Hello
+    NotAPeer,
+    IncompatibleVersion { local: u16, remote: u16 },
+    // The connection closed before the handshake finished
+    Closed,
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::Io(e) => write!(f, "i/o error: {}", e),
+            WireError::Codec(e) => write!(f, "malformed frame: {}", e),
+            WireError::FrameTooLarge(len) => write!(f, "frame of {} bytes exceeds the {} byte limit", len, MAX_FRAME_LEN),
+            WireError::UnknownTag(tag) => write!(f, "unknown message type 0x{:02x}", tag),
+            WireError::UnexpectedFrame(tag) => write!(f, "unexpected message type 0x{:02x}", tag),
+            WireError::NotAPeer => write!(f, "connection did not open with a DHT handshake"),
+            WireError::IncompatibleVersion { local, remote } => write!(
+                f,
+                "incompatible protocol version: peer speaks v{}, this node speaks v{}",
+                remote, local
+            ),
+            WireError::Closed => write!(f, "connection closed during handshake"),
+        }
+    }
+}
+
+impl From<io::Error> for WireError {
+    fn from(e: io::Error) -> Self {
+        WireError::Io(e)
+    }
+}
+
+impl From<postcard::Error> for WireError {
+    fn from(e: postcard::Error) -> Self {
+        WireError::Codec(e)
+    }
+}
+
+fn is_compatible(version: u16) -> bool {
+    version == PROTOCOL_VERSION
+}
+
+struct Frame {
+    tag: u8,
+    body: Vec<u8>,
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(stream: &mut W, tag: u8, body: &[u8]) -> Result<(), WireError> {
+    let len = body.len() + 1;
+    let len = u32::try_from(len)
+        .ok()
+        .filter(|&len| len <= MAX_FRAME_LEN)
+        .ok_or(WireError::FrameTooLarge(len))?;
+    // One write per frame so a reader never sees half a frame before we hang up
+    let mut frame = Vec::with_capacity(len as usize + 4);
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.push(tag);
+    frame.extend_from_slice(body);
+    stream.write_all(&frame).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+// None when the other end closed the connection between frames
+async fn read_frame<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<Frame>, WireError> {
+    let len = match stream.read_u32().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len == 0 || len > MAX_FRAME_LEN {
+        return Err(WireError::FrameTooLarge(len as usize));
+    }
+    let tag = stream.read_u8().await?;
+    let mut body = vec![0; len as usize - 1];
+    stream.read_exact(&mut body).await?;
+    Ok(Some(Frame { tag, body }))
+}
+
+fn encode<T: Serialize>(tag: u8, message: &T) -> Result<(u8, Vec<u8>), WireError> {
+    Ok((tag, postcard::to_stdvec(message)?))
+}
+
+fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T, WireError> {
+    Ok(postcard::from_bytes(body)?)
+}
+
+// Split items into runs whose encoding fits in one frame, for requests too big to send whole.
+// An item too large for a frame on its own still gets a run, and fails when it is written.
+pub fn batches<T: Serialize>(items: Vec<T>) -> Result<Vec<Vec<T>>, WireError> {
+    let limit = MAX_FRAME_LEN as usize - BATCH_OVERHEAD;
+    let mut batches: Vec<Vec<T>> = Vec::new();
+    let mut batch_len = 0;
+    for item in items {
+        let len = postcard::to_stdvec(&item)?.len();
+        match batches.last_mut() {
+            Some(batch) if batch_len + len <= limit => batch.push(item),
+            _ => {
+                batches.push(vec![item]);
+                batch_len = 0;
+            }
+        }
+        batch_len += len;
+    }
+    Ok(batches)
+}
+
+// The Hello body is raw bytes rather than postcard so any future version can still read it
+fn hello_body(version: u16) -> Vec<u8> {
+    let mut body = MAGIC.to_vec();
+    body.extend_from_slice(&version.to_be_bytes());
+    body
+}
+
+fn version_from(body: &[u8]) -> Option<u16> {
+    body.try_into().ok().map(u16::from_be_bytes)
+}
+
+// Client side of the handshake: announce our version and wait for the peer to accept it
+pub async fn client_handshake<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Result<(), WireError> {
+    handshake_as(stream, PROTOCOL_VERSION).await
+}
+
+async fn handshake_as<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, version: u16) -> Result<(), WireError> {
+    write_frame(stream, tag::HELLO, &hello_body(version)).await?;
+    let frame = read_frame(stream).await?.ok_or(WireError::Closed)?;
+    match frame.tag {
+        tag::HELLO_ACK => Ok(()),
+        tag::VERSION_MISMATCH => Err(WireError::IncompatibleVersion {
+            local: version,
+            remote: version_from(&frame.body).unwrap_or_default(),
+        }),
+        other => Err(WireError::UnexpectedFrame(other)),
+    }
+}
+
+// Server side of the handshake: accept a compatible Hello, otherwise tell the client our version and give up
+pub async fn server_handshake<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Result<(), WireError> {
+    let frame = match read_frame(stream).await {
+        Ok(Some(frame)) => frame,
+        Ok(None) => return Err(WireError::Closed),
+        // Whatever this is, it is not a length-prefixed Hello
+        Err(WireError::FrameTooLarge(_)) => return Err(WireError::NotAPeer),
+        Err(e) => return Err(e),
+    };
+    if frame.tag != tag::HELLO || frame.body.len() != MAGIC.len() + 2 || frame.body[..MAGIC.len()] != MAGIC {
+        return Err(WireError::NotAPeer);
+    }
+    let remote = version_from(&frame.body[MAGIC.len()..]).unwrap_or_default();
+    if !is_compatible(remote) {
+        write_frame(stream, tag::VERSION_MISMATCH, &PROTOCOL_VERSION.to_be_bytes()).await?;
+        return Err(WireError::IncompatibleVersion { local: PROTOCOL_VERSION, remote });
+    }
+    write_frame(stream, tag::HELLO_ACK, &PROTOCOL_VERSION.to_be_bytes()).await
+}
+
+pub async fn write_request<W: AsyncWrite + Unpin>(stream: &mut W, request: &RpcRequest) -> Result<(), WireError> {
+    let (tag, body) = match request {
+        RpcRequest::FindSuccessor(msg) => encode(tag::FIND_SUCCESSOR, msg)?,
+        RpcRequest::NextHop(msg) => encode(tag::NEXT_HOP, msg)?,
+        RpcRequest::Notify(msg) => encode(tag::NOTIFY, msg)?,
+        RpcRequest::GetPredecessor(msg) => encode(tag::GET_PREDECESSOR, msg)?,
+        RpcRequest::GetSuccessorList(msg) => encode(tag::GET_SUCCESSOR_LIST, msg)?,
+        RpcRequest::Heartbeat(msg) => encode(tag::HEARTBEAT, msg)?,
+        RpcRequest::ReplicateData(msg) => encode(tag::REPLICATE_DATA, msg)?,
+        RpcRequest::TransferData(msg) => encode(tag::TRANSFER_DATA, msg)?,
+        RpcRequest::MigrateKeys(msg) => encode(tag::MIGRATE_KEYS, msg)?,
+        RpcRequest::NeighborLeaving(msg) => encode(tag::NEIGHBOR_LEAVING, msg)?,
+        RpcRequest::Identify(msg) => encode(tag::IDENTIFY, msg)?,
+        RpcRequest::GetReplica(msg) => encode(tag::GET_REPLICA, msg)?,
+        RpcRequest::InsertKeyValue(msg) => encode(tag::INSERT_KEY_VALUE, msg)?,
+        RpcRequest::GetKeyValue(msg) => encode(tag::GET_KEY_VALUE, msg)?,
+        RpcRequest::DeleteKeyValue(msg) => encode(tag::DELETE_KEY_VALUE, msg)?,
+    };
+    write_frame(stream, tag, &body).await
+}
+
+pub async fn read_request<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<RpcRequest>, WireError> {
+    let Some(Frame { tag, body }) = read_frame(stream).await? else {
+        return Ok(None);
+    };
+    let request = match tag {
+        tag::FIND_SUCCESSOR => RpcRequest::FindSuccessor(decode(&body)?),
+        tag::NEXT_HOP => RpcRequest::NextHop(decode(&body)?),
+        tag::NOTIFY => RpcRequest::Notify(decode(&body)?),
+        tag::GET_PREDECESSOR => RpcRequest::GetPredecessor(decode(&body)?),
+        tag::GET_SUCCESSOR_LIST => RpcRequest::GetSuccessorList(decode(&body)?),
+        tag::HEARTBEAT => RpcRequest::Heartbeat(decode(&body)?),
+        tag::REPLICATE_DATA => RpcRequest::ReplicateData(decode(&body)?),
+        tag::TRANSFER_DATA => RpcRequest::TransferData(decode(&body)?),
+        tag::MIGRATE_KEYS => RpcRequest::MigrateKeys(decode(&body)?),
+        tag::NEIGHBOR_LEAVING => RpcRequest::NeighborLeaving(decode(&body)?),
+        tag::IDENTIFY => RpcRequest::Identify(decode(&body)?),
+        tag::GET_REPLICA => RpcRequest::GetReplica(decode(&body)?),
+        tag::INSERT_KEY_VALUE => RpcRequest::InsertKeyValue(decode(&body)?),
+        tag::GET_KEY_VALUE => RpcRequest::GetKeyValue(decode(&body)?),
+        tag::DELETE_KEY_VALUE => RpcRequest::DeleteKeyValue(decode(&body)?),
+        tag::HELLO | tag::HELLO_ACK | tag::VERSION_MISMATCH => return Err(WireError::UnexpectedFrame(tag)),
+        other => return Err(WireError::UnknownTag(other)),
+    };
+    Ok(Some(request))
+}
+
+pub async fn write_response<W: AsyncWrite + Unpin>(stream: &mut W, response: &RpcResponse) -> Result<(), WireError> {
+    let (tag, body) = match response {
+        RpcResponse::Route(route) => encode(tag::ROUTE, route)?,
+        RpcResponse::NextHop(hop) => encode(tag::NEXT_HOP_REPLY, hop)?,
+        RpcResponse::Predecessor(predecessor) => encode(tag::PREDECESSOR, predecessor)?,
+        RpcResponse::Successors(successors) => encode(tag::SUCCESSORS, successors)?,
+        RpcResponse::KeysMoved(keys_moved) => encode(tag::KEYS_MOVED, keys_moved)?,
+        RpcResponse::Ack => (tag::ACK, Vec::new()),
+        RpcResponse::Error(message) => encode(tag::ERROR, message)?,
+        RpcResponse::NodeRef(node) => encode(tag::NODE_REF, node)?,
+        RpcResponse::Versions(versions) => encode(tag::VERSIONS, versions)?,
+        RpcResponse::QuorumFailed(failure) => encode(tag::QUORUM_FAILED, failure)?,
+    };
+    write_frame(stream, tag, &body).await
+}
+
+pub async fn read_response<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<RpcResponse>, WireError> {
+    let Some(Frame { tag, body }) = read_frame(stream).await? else {
+        return Ok(None);
+    };
+    let response = match tag {
+        tag::ROUTE => RpcResponse::Route(decode(&body)?),
+        tag::NEXT_HOP_REPLY => RpcResponse::NextHop(decode(&body)?),
+        tag::PREDECESSOR => RpcResponse::Predecessor(decode(&body)?),
+        tag::SUCCESSORS => RpcResponse::Successors(decode(&body)?),
+        tag::KEYS_MOVED => RpcResponse::KeysMoved(decode(&body)?),
+        tag::ACK => RpcResponse::Ack,
+        tag::ERROR => RpcResponse::Error(decode(&body)?),
+        tag::NODE_REF => RpcResponse::NodeRef(decode(&body)?),
+        tag::VERSIONS => RpcResponse::Versions(decode(&body)?),
+        tag::QUORUM_FAILED => RpcResponse::QuorumFailed(decode(&body)?),
+        tag::HELLO | tag::HELLO_ACK | tag::VERSION_MISMATCH => return Err(WireError::UnexpectedFrame(tag)),
+        other => return Err(WireError::UnknownTag(other)),
+    };
+    Ok(Some(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use tokio::io::duplex;
+    use crate::nodes::hlc::Timestamp;
+    use crate::nodes::identifier::Identifier;
+    use crate::nodes::node_actor::*;
+    use crate::nodes::quorum::{Consistency, QuorumFailure};
+    use crate::nodes::version::{Dot, VectorClock, Version};
+    use crate::storage::{KeyValue, Replica};
+
+    fn node(id: u64) -> NodeRef {
+        NodeRef { id: Identifier::new(id), address: "127.0.0.1".to_string(), port: 5081 + id as i32 }
+    }
+
+    fn versions() -> Vec<Version> {
+        let node = Identifier::new(7);
+        vec![Version {
+            value: Some("value".to_string()),
+            dot: Dot { node, counter: 3 },
+            context: "7:2,9:1".parse().unwrap(),
+            timestamp: Timestamp { wall_ms: 1_700_000_000_000, logical: 4, node },
+        }]
+    }
+
+    fn requests() -> Vec<RpcRequest> {
+        let key = crate::nodes::Key::new(vec![0, 0xff, b'k']);
+        vec![
+            RpcRequest::FindSuccessor(FindSuccessor { id: Identifier::new(12), path: vec![Identifier::new(1), Identifier::new(5)] }),
+            RpcRequest::NextHop(NextHopMessage { id: Identifier::new(12) }),
+            RpcRequest::Notify(NotifyJoin { new_node: node(1) }),
+            RpcRequest::GetPredecessor(GetPredecessor),
+            RpcRequest::GetSuccessorList(GetSuccessorList),
+            RpcRequest::Heartbeat(Heartbeat),
+            RpcRequest::ReplicateData(ReplicateData { key: key.clone(), versions: versions(), replica: Replica { owner: Identifier::new(7), rank: 2 } }),
+            RpcRequest::GetReplica(GetReplica { key: key.clone() }),
+            RpcRequest::TransferData(TransferData {
+                from: Identifier::new(7),
+                data: vec![KeyValue { key: key.clone(), versions: versions(), replica: Replica::owned_by(Identifier::new(9)) }],
+            }),
+            RpcRequest::MigrateKeys(MigrateKeys { to: node(2) }),
+            RpcRequest::NeighborLeaving(NeighborLeaving { leaving: Identifier::new(3), predecessor: Some(node(1)), successors: vec![node(4), node(5)] }),
+            RpcRequest::Identify(GetNodeRef),
+            RpcRequest::InsertKeyValue(InsertKeyValue {
+                key: key.clone(),
+                value: "value".to_string(),
+                context: VectorClock::default(),
+                consistency: Consistency::Quorum,
+            }),
+            RpcRequest::GetKeyValue(GetKeyValue { key: key.clone(), consistency: Consistency::Count(2) }),
+            RpcRequest::DeleteKeyValue(DeleteKeyValue { key, consistency: Consistency::All }),
+        ]
+    }
+
+    fn responses() -> Vec<RpcResponse> {
+        vec![
+            RpcResponse::Route(Some(Route { owner: node(3), path: vec![Identifier::new(1), Identifier::new(3)] })),
+            RpcResponse::NextHop(NextHop::Forward(node(4))),
+            RpcResponse::Predecessor(None),
+            RpcResponse::Successors(vec![node(1), node(2)]),
+            RpcResponse::KeysMoved(42),
+            RpcResponse::NodeRef(node(6)),
+            RpcResponse::Versions(versions()),
+            RpcResponse::Ack,
+            RpcResponse::Error("disk full".to_string()),
+            RpcResponse::QuorumFailed(QuorumFailure::NotMet("quorum not met".to_string())),
+        ]
+    }
+
+    async fn encode_request(request: &RpcRequest) -> Vec<u8> {
+        let mut frame = Vec::new();
+        write_request(&mut frame, request).await.unwrap();
+        frame
+    }
+
+    async fn encode_response(response: &RpcResponse) -> Vec<u8> {
+        let mut frame = Vec::new();
+        write_response(&mut frame, response).await.unwrap();
+        frame
+    }
+
+    #[actix::test]
+    async fn matching_versions_complete_the_handshake() {
+        let (mut client, mut server) = duplex(1024);
+        let (client, server) = tokio::join!(client_handshake(&mut client), server_handshake(&mut server));
+        assert!(client.is_ok());
+        assert!(server.is_ok());
+    }
+
+    #[actix::test]
+    async fn mismatched_versions_are_rejected_on_both_sides() {
+        let other = PROTOCOL_VERSION + 1;
+        let (mut client, mut server) = duplex(1024);
+        let (client, server) = tokio::join!(handshake_as(&mut client, other), server_handshake(&mut server));
+        assert!(matches!(
+            client,
+            Err(WireError::IncompatibleVersion { local, remote }) if local == other && remote == PROTOCOL_VERSION
+        ));
+        assert!(matches!(
+            server,
+            Err(WireError::IncompatibleVersion { local, remote }) if local == PROTOCOL_VERSION && remote == other
+        ));
+    }
+
+    #[actix::test]
+    async fn other_protocols_are_not_peers() {
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        assert!(matches!(server_handshake(&mut server).await, Err(WireError::NotAPeer)));
+
+        // A well-formed frame that is not a DHT Hello
+        let (mut client, mut server) = duplex(1024);
+        write_frame(&mut client, tag::HELLO, b"HTTP\x00\x01").await.unwrap();
+        assert!(matches!(server_handshake(&mut server).await, Err(WireError::NotAPeer)));
+    }
+
+    #[actix::test]
+    async fn every_request_round_trips() {
+        let mut tags = HashSet::new();
+        for request in requests() {
+            let frame = encode_request(&request).await;
+            assert!(tags.insert(frame[4]), "tag 0x{:02x} is used twice", frame[4]);
+            let decoded = read_request(&mut frame.as_slice()).await.unwrap().unwrap();
+            assert_eq!(encode_request(&decoded).await, frame);
+        }
+    }
+
+    #[actix::test]
+    async fn every_response_round_trips() {
+        let mut tags = HashSet::new();
+        for response in responses() {
+            let frame = encode_response(&response).await;
+            assert!(tags.insert(frame[4]), "tag 0x{:02x} is used twice", frame[4]);
+            let decoded = read_response(&mut frame.as_slice()).await.unwrap().unwrap();
+            assert_eq!(encode_response(&decoded).await, frame);
+        }
+    }
+
+    #[actix::test]
+    async fn unknown_tags_are_rejected() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, 0x7f, &[]).await.unwrap();
+        assert!(matches!(read_request(&mut frame.as_slice()).await, Err(WireError::UnknownTag(0x7f))));
+        assert!(matches!(read_response(&mut frame.as_slice()).await, Err(WireError::UnknownTag(0x7f))));
+    }
+
+    #[test]
+    fn batches_stay_under_the_frame_limit() {
+        let rows = vec!["x".repeat(1024 * 1024); 40];
+        let batches = batches(rows).unwrap();
+        assert!(batches.len() >= 3);
+        assert_eq!(batches.iter().map(Vec::len).sum::<usize>(), 40);
+        for batch in &batches {
+            assert!(postcard::to_stdvec(batch).unwrap().len() + BATCH_OVERHEAD <= MAX_FRAME_LEN as usize);
+        }
+    }
+
+    #[test]
+    fn small_requests_go_in_one_batch() {
+        assert_eq!(batches(vec![1u32, 2, 3]).unwrap(), vec![vec![1, 2, 3]]);
+        assert!(batches(Vec::<u32>::new()).unwrap().is_empty());
+    }
+}
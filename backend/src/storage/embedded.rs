@@ -0,0 +1,524 @@
+use redb::{Database, ReadableTable, TableDefinition, TableHandle, WriteTransaction};
+use serde::Deserialize;
+use std::io;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+use super::{is_empty_range, Change, KeyCounts, KeyRange, KeyValue, Replica, Storage, StorageError, StorageFuture};
+use crate::nodes::hlc::Timestamp;
+use crate::nodes::version::{live, ConflictPolicy, Dot, VectorClock, Version};
+use crate::nodes::{Identifier, Key};
+
+// Node id as 8 big-endian bytes followed by the key's bytes -> (owner id, replica rank, postcard-encoded versions),
+// so each node's rows sort together by key
+const KEY_VALUES: TableDefinition<&[u8], (u64, u8, &[u8])> = TableDefinition::new("key_values");
+
+// Which layout KEY_VALUES is in, keyed by table name. Bump FORMAT_VERSION whenever the row layout
+// changes and teach `upgrade` to convert rows from the previous one.
+const FORMAT: TableDefinition<&str, u32> = TableDefinition::new("format");
+const FORMAT_VERSION: u32 = 6;
+
+// Where earlier layouts kept their rows; `upgrade` converts and drops them
+// 1: (node id, integer key) -> value
+const V1_ROWS: TableDefinition<(u64, i32), &str> = TableDefinition::new("key_values");
+// 2: row key -> value
+const V2_ROWS: TableDefinition<&[u8], &str> = TableDefinition::new("keys");
+// 3: row key -> (owner id, replica rank, value)
+const V3_ROWS: TableDefinition<&[u8], (u64, u8, &str)> = TableDefinition::new("key_rows");
+// 4: row key -> (owner id, replica rank, postcard-encoded versions without timestamps)
+const V4_ROWS: TableDefinition<&[u8], (u64, u8, &[u8])> = TableDefinition::new("key_versions");
+// 5: row key -> (owner id, replica rank, postcard-encoded versions that can't be tombstones), first under
+// its own name and then as KEY_VALUES
+const V5_ROWS: TableDefinition<&[u8], (u64, u8, &[u8])> = TableDefinition::new("stamped_versions");
+
+// A version as layout 4 stored it, before versions were timestamped
+#[derive(Deserialize)]
+struct UnstampedVersion {
+    value: String,
+    dot: Dot,
+    context: VectorClock,
+}
+
+// A version as layout 5 stored it, before deletes left tombstones
+#[derive(Deserialize)]
+struct LiveVersion {
+    value: String,
+    dot: Dot,
+    context: VectorClock,
+    timestamp: Timestamp,
+}
+
+// Like the Postgres migrations: a value stored before versioning becomes one version with counter 0,
+// and a version stored before timestamps gets the earliest one, from the node that coordinated it
+fn earliest(node: Identifier) -> Timestamp {
+    Timestamp { wall_ms: 0, logical: 0, node }
+}
+
+fn unversioned(value: &str, owner: Identifier) -> Vec<Version> {
+    vec![Version {
+        value: Some(value.to_string()),
+        dot: Dot { node: owner, counter: 0 },
+        context: VectorClock::default(),
+        timestamp: earliest(owner),
+    }]
+}
+
+fn stamped(version: UnstampedVersion) -> Version {
+    let timestamp = earliest(version.dot.node);
+    Version { value: Some(version.value), dot: version.dot, context: version.context, timestamp }
+}
+
+fn decode_live(bytes: &[u8]) -> Result<Vec<Version>, StorageError> {
+    let versions: Vec<LiveVersion> = postcard::from_bytes(bytes).map_err(|e| StorageError::Corrupt(e.to_string()))?;
+    Ok(versions
+        .into_iter()
+        .map(|version| Version {
+            value: Some(version.value),
+            dot: version.dot,
+            context: version.context,
+            timestamp: version.timestamp,
+        })
+        .collect())
+}
+
+// The node a row key belongs to
+fn row_node(row_key: &[u8]) -> Identifier {
+    let mut node = [0; 8];
+    node.copy_from_slice(&row_key[..8]);
+    Identifier::new(u64::from_be_bytes(node))
+}
+
+// A row in the current layout: row key, owner id, replica rank, versions
+type Row = (Vec<u8>, u64, u8, Vec<Version>);
+
+// Bring a store written by an earlier release to the current layout, in the transaction that opens it
+fn upgrade(tx: &WriteTransaction) -> Result<(), StorageError> {
+    let format = tx.open_table(FORMAT)?.get(KEY_VALUES.name())?.map(|format| format.value());
+    match format {
+        Some(FORMAT_VERSION) => return Ok(()),
+        Some(5) | None => {}
+        Some(other) => {
+            return Err(StorageError::Corrupt(format!(
+                "the store's rows are in layout {}, this release reads layout {}",
+                other, FORMAT_VERSION
+            )))
+        }
+    }
+    let tables: Vec<String> = tx.list_tables()?.map(|table| table.name().to_string()).collect();
+    let present = |name: &str| tables.iter().any(|table| table == name);
+
+    let mut rows: Vec<Row> = Vec::new();
+    // Layout 1 used the name the current layout has now; without a format entry the table is the old one
+    if format.is_none() && present(V1_ROWS.name()) {
+        for row in tx.open_table(V1_ROWS)?.iter()? {
+            let (key, value) = row?;
+            let (node, key) = key.value();
+            // Integer keys keep their decimal text, which is also what they were hashed by
+            let row_key = row_key(Identifier::new(node), key.to_string().as_bytes());
+            rows.push((row_key, node, 0, unversioned(value.value(), Identifier::new(node))));
+        }
+        tx.delete_table(V1_ROWS)?;
+    }
+    if present(V2_ROWS.name()) {
+        for row in tx.open_table(V2_ROWS)?.iter()? {
+            let (key, value) = row?;
+            // Rows stored before replicas were tagged are the holder's own
+            let owner = row_node(key.value());
+            rows.push((key.value().to_vec(), owner.value(), 0, unversioned(value.value(), owner)));
+        }
+        tx.delete_table(V2_ROWS)?;
+    }
+    if present(V3_ROWS.name()) {
+        for row in tx.open_table(V3_ROWS)?.iter()? {
+            let (key, row) = row?;
+            let (owner, rank, value) = row.value();
+            rows.push((key.value().to_vec(), owner, rank, unversioned(value, Identifier::new(owner))));
+        }
+        tx.delete_table(V3_ROWS)?;
+    }
+    if present(V4_ROWS.name()) {
+        for row in tx.open_table(V4_ROWS)?.iter()? {
+            let (key, row) = row?;
+            let (owner, rank, versions) = row.value();
+            let versions: Vec<UnstampedVersion> =
+                postcard::from_bytes(versions).map_err(|e| StorageError::Corrupt(e.to_string()))?;
+            rows.push((key.value().to_vec(), owner, rank, versions.into_iter().map(stamped).collect()));
+        }
+        tx.delete_table(V4_ROWS)?;
+    }
+    if present(V5_ROWS.name()) {
+        for row in tx.open_table(V5_ROWS)?.iter()? {
+            let (key, row) = row?;
+            let (owner, rank, versions) = row.value();
+            rows.push((key.value().to_vec(), owner, rank, decode_live(versions)?));
+        }
+        tx.delete_table(V5_ROWS)?;
+    }
+    if format == Some(5) {
+        for row in tx.open_table(KEY_VALUES)?.iter()? {
+            let (key, row) = row?;
+            let (owner, rank, versions) = row.value();
+            rows.push((key.value().to_vec(), owner, rank, decode_live(versions)?));
+        }
+    }
+
+    let mut table = tx.open_table(KEY_VALUES)?;
+    for (row_key, owner, rank, versions) in rows {
+        let versions = encode(&versions)?;
+        table.insert(row_key.as_slice(), (owner, rank, versions.as_slice()))?;
+    }
+    tx.open_table(FORMAT)?.insert(KEY_VALUES.name(), FORMAT_VERSION)?;
+    Ok(())
+}
+
+fn row_key(node: Identifier, key: &[u8]) -> Vec<u8> {
+    let mut row = node.value().to_be_bytes().to_vec();
+    row.extend_from_slice(key);
+    row
+}
+
+// The row keys bounding `range` within one node's rows
+fn row_range(node: Identifier, range: &KeyRange) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let start = match &range.0 {
+        Bound::Included(key) => Bound::Included(row_key(node, key.as_bytes())),
+        Bound::Excluded(key) => Bound::Excluded(row_key(node, key.as_bytes())),
+        Bound::Unbounded => Bound::Included(row_key(node, &[])),
+    };
+    let end = match &range.1 {
+        Bound::Included(key) => Bound::Included(row_key(node, key.as_bytes())),
+        Bound::Excluded(key) => Bound::Excluded(row_key(node, key.as_bytes())),
+        // Up to where the next node's rows start
+        Bound::Unbounded => match node.value().checked_add(1) {
+            Some(next) => Bound::Excluded(next.to_be_bytes().to_vec()),
+            None => Bound::Unbounded,
+        },
+    };
+    (start, end)
+}
+
+fn encode(versions: &[Version]) -> Result<Vec<u8>, StorageError> {
+    postcard::to_allocvec(versions).map_err(|e| StorageError::Corrupt(e.to_string()))
+}
+
+fn decode(bytes: &[u8]) -> Result<Vec<Version>, StorageError> {
+    postcard::from_bytes(bytes).map_err(|e| StorageError::Corrupt(e.to_string()))
+}
+
+fn as_slices(range: &(Bound<Vec<u8>>, Bound<Vec<u8>>)) -> (Bound<&[u8]>, Bound<&[u8]>) {
+    (range.0.as_ref().map(Vec::as_slice), range.1.as_ref().map(Vec::as_slice))
+}
+
+// Rows kept in a redb file on local disk, so a node needs no database server
+#[derive(Debug, Clone)]
+pub struct EmbeddedStorage {
+    db: Arc<Database>,
+}
+
+impl EmbeddedStorage {
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        let db = Database::create(path)?;
+        // Convert rows left by an earlier release and create the table up front so readers never find it missing
+        let tx = db.begin_write()?;
+        upgrade(&tx)?;
+        tx.open_table(KEY_VALUES)?;
+        tx.commit()?;
+        Ok(EmbeddedStorage { db: Arc::new(db) })
+    }
+
+    // `update`, or `replicate` when `keep_owned`
+    fn write(&self, node: Identifier, key: Key, replica: Replica, change: Change, keep_owned: bool) -> StorageFuture<Vec<Version>> {
+        self.run(move |db| {
+            // redb runs one write transaction at a time, which serializes the read and the write below
+            let tx = db.begin_write()?;
+            let kept = {
+                let mut table = tx.open_table(KEY_VALUES)?;
+                let row_key = row_key(node, key.as_bytes());
+                let (current, tag) = match table.get(row_key.as_slice())? {
+                    Some(row) => {
+                        let (owner, rank, versions) = row.value();
+                        (decode(versions)?, Some(Replica { owner: Identifier::new(owner), rank }))
+                    }
+                    None => (Vec::new(), None),
+                };
+                let replica = if keep_owned { replica.replicated_onto(node, tag) } else { replica };
+                let kept = change(current);
+                match kept.is_empty() {
+                    true => {
+                        table.remove(row_key.as_slice())?;
+                    }
+                    false => {
+                        let versions = encode(&kept)?;
+                        table.insert(row_key.as_slice(), (replica.owner.value(), replica.rank, versions.as_slice()))?;
+                    }
+                }
+                kept
+            };
+            tx.commit()?;
+            Ok(kept)
+        })
+    }
+
+    // redb blocks on disk I/O, so run each operation off the actor's thread
+    fn run<T, F>(&self, op: F) -> StorageFuture<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Database) -> Result<T, StorageError> + Send + 'static,
+    {
+        let db = self.db.clone();
+        Box::pin(async move {
+            match tokio::task::spawn_blocking(move || op(&db)).await {
+                Ok(result) => result,
+                Err(e) => Err(redb::Error::Io(io::Error::other(e)).into()),
+            }
+        })
+    }
+}
+
+impl Storage for EmbeddedStorage {
+    fn update(&self, node: Identifier, key: Key, replica: Replica, change: Change) -> StorageFuture<Vec<Version>> {
+        self.write(node, key, replica, change, false)
+    }
+
+    fn replicate(&self, node: Identifier, key: Key, replica: Replica, change: Change) -> StorageFuture<Vec<Version>> {
+        self.write(node, key, replica, change, true)
+    }
+
+    fn get(&self, node: Identifier, key: Key) -> StorageFuture<Vec<Version>> {
+        self.run(move |db| {
+            let table = db.begin_read()?.open_table(KEY_VALUES)?;
+            match table.get(row_key(node, key.as_bytes()).as_slice())? {
+                Some(row) => decode(row.value().2),
+                None => Ok(Vec::new()),
+            }
+        })
+    }
+
+    fn delete(&self, node: Identifier, key: Key) -> StorageFuture<bool> {
+        self.run(move |db| {
+            let tx = db.begin_write()?;
+            let existed = tx
+                .open_table(KEY_VALUES)?
+                .remove(row_key(node, key.as_bytes()).as_slice())?
+                .is_some();
+            tx.commit()?;
+            Ok(existed)
+        })
+    }
+
+    fn scan(&self, node: Identifier, range: KeyRange) -> StorageFuture<Vec<KeyValue>> {
+        self.run(move |db| {
+            let mut rows = Vec::new();
+            if is_empty_range(&range) {
+                return Ok(rows);
+            }
+            let table = db.begin_read()?.open_table(KEY_VALUES)?;
+            let bounds = row_range(node, &range);
+            for row in table.range::<&[u8]>(as_slices(&bounds))? {
+                let (key, row) = row?;
+                let (owner, rank, versions) = row.value();
+                rows.push(KeyValue {
+                    key: Key::new(&key.value()[8..]),
+                    versions: decode(versions)?,
+                    replica: Replica { owner: Identifier::new(owner), rank },
+                });
+            }
+            Ok(rows)
+        })
+    }
+
+    fn count(&self, node: Identifier) -> StorageFuture<KeyCounts> {
+        self.run(move |db| {
+            let table = db.begin_read()?.open_table(KEY_VALUES)?;
+            let bounds = row_range(node, &super::ALL_KEYS);
+            let mut counts = KeyCounts::default();
+            for row in table.range::<&[u8]>(as_slices(&bounds))? {
+                let (_, row) = row?;
+                let (_, rank, versions) = row.value();
+                if live(&decode(versions)?).is_empty() {
+                    continue;
+                }
+                match rank {
+                    0 => counts.owned += 1,
+                    _ => counts.replicas += 1,
+                }
+            }
+            Ok(counts)
+        })
+    }
+
+    fn transfer(&self, from: Identifier, to: Identifier, data: Vec<KeyValue>, conflicts: ConflictPolicy) -> StorageFuture<()> {
+        self.run(move |db| {
+            let tx = db.begin_write()?;
+            {
+                let mut table = tx.open_table(KEY_VALUES)?;
+                for kv in data {
+                    table.remove(row_key(from, kv.key.as_bytes()).as_slice())?;
+                    let row_key = row_key(to, kv.key.as_bytes());
+                    let current = match table.get(row_key.as_slice())? {
+                        Some(row) => decode(row.value().2)?,
+                        None => Vec::new(),
+                    };
+                    let versions = encode(&conflicts.merge(&kv.key, current, kv.versions))?;
+                    table.insert(row_key.as_slice(), (kv.replica.owner.value(), kv.replica.rank, versions.as_slice()))?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::storage::ALL_KEYS;
+
+    struct TempStore(PathBuf);
+
+    impl TempStore {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("dht-embedded-{}-{}.redb", name, std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            TempStore(path)
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn write_legacy<F: FnOnce(&WriteTransaction) -> Result<(), StorageError>>(path: &Path, write: F) {
+        let db = Database::create(path).unwrap();
+        let tx = db.begin_write().unwrap();
+        write(&tx).unwrap();
+        tx.commit().unwrap();
+    }
+
+    #[actix::test]
+    async fn copies_from_the_owner_never_demote_an_owned_row() {
+        let store = TempStore::new("replicate");
+        let storage = EmbeddedStorage::open(&store.0).unwrap();
+        let (node, key) = (Identifier::new(3), Key::new(b"k".to_vec()));
+        let append = |value: &str, owner: u64| -> Change {
+            let versions = unversioned(value, Identifier::new(owner));
+            Box::new(move |mut current: Vec<Version>| {
+                current.extend(versions);
+                current
+            })
+        };
+        let tag = |storage: &EmbeddedStorage| {
+            let storage = storage.clone();
+            async move { storage.scan(node, ALL_KEYS).await.unwrap()[0].replica }
+        };
+        let stale = Replica { owner: Identifier::new(9), rank: 1 };
+
+        storage.update(node, key.clone(), Replica::owned_by(node), append("ours", 3)).await.unwrap();
+        let kept = storage.replicate(node, key.clone(), stale, append("theirs", 9)).await.unwrap();
+        assert_eq!(kept.len(), 2);
+        assert_eq!(tag(&storage).await, Replica::owned_by(node));
+
+        // Handing the key over still demotes it, and then copies retag it as they come
+        storage.update(node, key.clone(), stale, append("more", 3)).await.unwrap();
+        assert_eq!(tag(&storage).await, stale);
+        let moved = Replica { owner: Identifier::new(9), rank: 2 };
+        storage.replicate(node, key.clone(), moved, append("again", 9)).await.unwrap();
+        assert_eq!(tag(&storage).await, moved);
+    }
+
+    #[actix::test]
+    async fn integer_keyed_rows_are_converted_on_open() {
+        let store = TempStore::new("v1");
+        write_legacy(&store.0, |tx| {
+            tx.open_table(V1_ROWS)?.insert((7, 42), "answer")?;
+            Ok(())
+        });
+
+        let storage = EmbeddedStorage::open(&store.0).unwrap();
+        let versions = storage.get(Identifier::new(7), Key::new(b"42".to_vec())).await.unwrap();
+        assert_eq!(versions, unversioned("answer", Identifier::new(7)));
+        let rows = storage.scan(Identifier::new(7), ALL_KEYS).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].replica, Replica::owned_by(Identifier::new(7)));
+    }
+
+    #[actix::test]
+    async fn replica_tags_and_unstamped_versions_are_kept() {
+        let store = TempStore::new("v3-v4");
+        let node = Identifier::new(3);
+        let unstamped = postcard::to_allocvec(&vec![(
+            "new".to_string(),
+            Dot { node: Identifier::new(9), counter: 2 },
+            "9:2".parse::<VectorClock>().unwrap(),
+        )])
+        .unwrap();
+        write_legacy(&store.0, |tx| {
+            tx.open_table(V3_ROWS)?.insert(row_key(node, b"a").as_slice(), (9, 1, "old"))?;
+            tx.open_table(V4_ROWS)?.insert(row_key(node, b"b").as_slice(), (9, 2, unstamped.as_slice()))?;
+            Ok(())
+        });
+
+        let storage = EmbeddedStorage::open(&store.0).unwrap();
+        let rows = storage.scan(node, ALL_KEYS).await.unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].key, Key::new(b"a".to_vec()));
+        assert_eq!(rows[0].replica, Replica { owner: Identifier::new(9), rank: 1 });
+        assert_eq!(rows[0].versions, unversioned("old", Identifier::new(9)));
+        assert_eq!(rows[1].replica, Replica { owner: Identifier::new(9), rank: 2 });
+        assert_eq!(rows[1].versions.len(), 1);
+        assert_eq!(rows[1].versions[0].dot, Dot { node: Identifier::new(9), counter: 2 });
+        assert_eq!(rows[1].versions[0].timestamp, earliest(Identifier::new(9)));
+
+        // The old tables are gone, so opening again converts nothing twice
+        drop(storage);
+        let storage = EmbeddedStorage::open(&store.0).unwrap();
+        assert_eq!(storage.scan(node, ALL_KEYS).await.unwrap().len(), 2);
+    }
+
+    #[actix::test]
+    async fn rows_without_tombstones_are_converted_in_place() {
+        let store = TempStore::new("v5");
+        let node = Identifier::new(4);
+        let timestamp = Timestamp { wall_ms: 1_700_000_000_000, logical: 2, node };
+        let live = postcard::to_allocvec(&vec![("kept".to_string(), Dot { node, counter: 1 }, VectorClock::default(), timestamp)]).unwrap();
+        write_legacy(&store.0, |tx| {
+            tx.open_table(KEY_VALUES)?.insert(row_key(node, b"k").as_slice(), (4, 0, live.as_slice()))?;
+            tx.open_table(FORMAT)?.insert(KEY_VALUES.name(), 5)?;
+            Ok(())
+        });
+
+        let storage = EmbeddedStorage::open(&store.0).unwrap();
+        let versions = storage.get(node, Key::new(b"k".to_vec())).await.unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].value.as_deref(), Some("kept"));
+        assert_eq!(versions[0].timestamp, timestamp);
+    }
+
+    #[actix::test]
+    async fn deleted_keys_are_kept_but_not_counted() {
+        let store = TempStore::new("tombstones");
+        let storage = EmbeddedStorage::open(&store.0).unwrap();
+        let node = Identifier::new(2);
+        let mut tombstone = unversioned("gone", node);
+        tombstone[0].value = None;
+        let kept = tombstone.clone();
+        storage.update(node, Key::new(b"gone".to_vec()), Replica::owned_by(node), Box::new(move |_| kept)).await.unwrap();
+        let live = unversioned("here", node);
+        storage.update(node, Key::new(b"here".to_vec()), Replica::owned_by(node), Box::new(move |_| live)).await.unwrap();
+
+        assert_eq!(storage.get(node, Key::new(b"gone".to_vec())).await.unwrap(), tombstone);
+        assert_eq!(storage.count(node).await.unwrap().owned, 1);
+    }
+
+    #[test]
+    fn a_newer_layout_is_refused() {
+        let store = TempStore::new("newer");
+        write_legacy(&store.0, |tx| {
+            tx.open_table(FORMAT)?.insert(KEY_VALUES.name(), FORMAT_VERSION + 1)?;
+            Ok(())
+        });
+        assert!(matches!(EmbeddedStorage::open(&store.0), Err(StorageError::Corrupt(_))));
+    }
+}
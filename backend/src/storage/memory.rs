@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use super::{is_empty_range, Change, KeyCounts, KeyRange, KeyValue, Replica, Storage, StorageFuture};
+use crate::nodes::version::{live, ConflictPolicy, Version};
+use crate::nodes::{Identifier, Key};
+
+// One node's rows: key -> (versions, replica tag)
+type NodeRows = BTreeMap<Key, (Vec<Version>, Replica)>;
+
+// Rows kept in process memory and lost on exit; for tests and throwaway rings
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    rows: Arc<Mutex<BTreeMap<Identifier, NodeRows>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+
+    // `update`, or `replicate` when `keep_owned`
+    fn write(&self, node: Identifier, key: Key, replica: Replica, change: Change, keep_owned: bool) -> StorageFuture<Vec<Version>> {
+        let mut rows = self.rows.lock().unwrap();
+        let node_rows = rows.entry(node).or_default();
+        let (current, tag) = match node_rows.remove(&key) {
+            Some((versions, tag)) => (versions, Some(tag)),
+            None => (Vec::new(), None),
+        };
+        let replica = if keep_owned { replica.replicated_onto(node, tag) } else { replica };
+        let kept = change(current);
+        if !kept.is_empty() {
+            node_rows.insert(key, (kept.clone(), replica));
+        }
+        Box::pin(async move { Ok(kept) })
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn update(&self, node: Identifier, key: Key, replica: Replica, change: Change) -> StorageFuture<Vec<Version>> {
+        self.write(node, key, replica, change, false)
+    }
+
+    fn replicate(&self, node: Identifier, key: Key, replica: Replica, change: Change) -> StorageFuture<Vec<Version>> {
+        self.write(node, key, replica, change, true)
+    }
+
+    fn get(&self, node: Identifier, key: Key) -> StorageFuture<Vec<Version>> {
+        let versions = self
+            .rows
+            .lock()
+            .unwrap()
+            .get(&node)
+            .and_then(|rows| rows.get(&key).map(|(versions, _)| versions.clone()))
+            .unwrap_or_default();
+        Box::pin(async move { Ok(versions) })
+    }
+
+    fn delete(&self, node: Identifier, key: Key) -> StorageFuture<bool> {
+        let existed = self
+            .rows
+            .lock()
+            .unwrap()
+            .get_mut(&node)
+            .is_some_and(|rows| rows.remove(&key).is_some());
+        Box::pin(async move { Ok(existed) })
+    }
+
+    fn scan(&self, node: Identifier, range: KeyRange) -> StorageFuture<Vec<KeyValue>> {
+        let rows: Vec<KeyValue> = match self.rows.lock().unwrap().get(&node) {
+            Some(rows) if !is_empty_range(&range) => rows
+                .range(range)
+                .map(|(key, (versions, replica))| KeyValue { key: key.clone(), versions: versions.clone(), replica: *replica })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Box::pin(async move { Ok(rows) })
+    }
+
+    fn count(&self, node: Identifier) -> StorageFuture<KeyCounts> {
+        let mut counts = KeyCounts::default();
+        let rows = self.rows.lock().unwrap();
+        let live_rows = rows.get(&node).into_iter().flat_map(NodeRows::values).filter(|(versions, _)| !live(versions).is_empty());
+        for (_, replica) in live_rows {
+            match replica.is_owner() {
+                true => counts.owned += 1,
+                false => counts.replicas += 1,
+            }
+        }
+        drop(rows);
+        Box::pin(async move { Ok(counts) })
+    }
+
+    fn transfer(&self, from: Identifier, to: Identifier, data: Vec<KeyValue>, conflicts: ConflictPolicy) -> StorageFuture<()> {
+        let mut rows = self.rows.lock().unwrap();
+        if let Some(from_rows) = rows.get_mut(&from) {
+            for kv in &data {
+                from_rows.remove(&kv.key);
+            }
+        }
+        let to_rows = rows.entry(to).or_default();
+        for kv in data {
+            let current = to_rows.remove(&kv.key).map(|(versions, _)| versions).unwrap_or_default();
+            let versions = conflicts.merge(&kv.key, current, kv.versions);
+            to_rows.insert(kv.key, (versions, kv.replica));
+        }
+        Box::pin(async { Ok(()) })
+    }
+}
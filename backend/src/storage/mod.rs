@@ -0,0 +1,181 @@
+// src/storage/mod.rs
+
+// Where nodes keep their key-value rows
+pub mod embedded;
+pub mod memory;
+pub mod postgres;
+pub mod registry;
+
+pub use embedded::EmbeddedStorage;
+pub use memory::MemoryStorage;
+pub use postgres::PostgresStorage;
+pub use registry::{MemoryRegistry, NodeRecord, NodeRegistry, PostgresRegistry};
+
+use actix::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Bound;
+use std::sync::Arc;
+use crate::config::db;
+use crate::config::settings::{RegistryBackend, StorageBackend, StorageSettings};
+use crate::nodes::version::{ConflictPolicy, Version};
+use crate::nodes::{Identifier, Key};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyValue {
+    pub key: Key,
+    // The key's concurrent values; one unless writers have conflicted
+    pub versions: Vec<Version>,
+    pub replica: Replica,
+}
+
+// Where a row sits in its key's replica set: the owner holds rank 0 and its successors ranks 1..N-1
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Replica {
+    pub owner: Identifier,
+    pub rank: u8,
+}
+
+// Ranks fit in a byte, so a key has at most this many copies
+pub const MAX_REPLICATION_FACTOR: usize = u8::MAX as usize + 1;
+
+impl Replica {
+    // The owner's own copy
+    pub fn owned_by(owner: Identifier) -> Self {
+        Replica { owner, rank: 0 }
+    }
+
+    pub fn is_owner(&self) -> bool {
+        self.rank == 0
+    }
+
+    // The tag a copy from the key's owner leaves on `node`'s row, tagged `current` so far:
+    // a key `node` owns stays owned, so a late or stale copy never demotes it
+    pub fn replicated_onto(self, node: Identifier, current: Option<Replica>) -> Replica {
+        match current {
+            Some(current) if current == Replica::owned_by(node) => current,
+            _ => self,
+        }
+    }
+}
+
+// Rows a node holds, split by whether it owns the key; keys that were deleted don't count
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct KeyCounts {
+    pub owned: usize,
+    pub replicas: usize,
+}
+
+pub type StorageFuture<T> = ResponseFuture<Result<T, StorageError>>;
+
+// Turns a key's stored versions into the ones to keep; no versions left removes the key
+pub type Change = Box<dyn FnOnce(Vec<Version>) -> Vec<Version> + Send>;
+
+// Key-value rows of the nodes in this process, shared across HTTP workers; every row belongs to one node
+pub trait Storage: fmt::Debug + Send + Sync {
+    // Apply `change` to the key's versions, none if it is new, and set its replica tag.
+    // Updates of one key are serialized, so no concurrent write is lost in between; returns what was kept.
+    fn update(&self, node: Identifier, key: Key, replica: Replica, change: Change) -> StorageFuture<Vec<Version>>;
+    // The same for a copy sent by the key's owner, except that a row `node` owns keeps its tag (see `Replica::replicated_onto`)
+    fn replicate(&self, node: Identifier, key: Key, replica: Replica, change: Change) -> StorageFuture<Vec<Version>>;
+    // The key's versions, none if it is missing
+    fn get(&self, node: Identifier, key: Key) -> StorageFuture<Vec<Version>>;
+    // Whether the key was there
+    fn delete(&self, node: Identifier, key: Key) -> StorageFuture<bool>;
+    // Rows whose key lies in `range`, in byte order
+    fn scan(&self, node: Identifier, range: KeyRange) -> StorageFuture<Vec<KeyValue>>;
+    fn count(&self, node: Identifier) -> StorageFuture<KeyCounts>;
+    // Give `to` the rows, tagged as they come and merged with any versions it already holds as `conflicts` says,
+    // and drop `from`'s copies in one step, so a key is never owned by both nodes or neither
+    fn transfer(&self, from: Identifier, to: Identifier, data: Vec<KeyValue>, conflicts: ConflictPolicy) -> StorageFuture<()>;
+}
+
+// Keys between two bounds, compared byte by byte
+pub type KeyRange = (Bound<Key>, Bound<Key>);
+
+// Every key a node holds
+pub const ALL_KEYS: KeyRange = (Bound::Unbounded, Bound::Unbounded);
+
+// A range whose start lies past its end holds no keys; BTreeMap::range would panic on it
+pub fn is_empty_range(range: &KeyRange) -> bool {
+    match range {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Included(end) | Bound::Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    Postgres(sqlx::Error),
+    // The embedded schema migrations could not be applied
+    Migrate(sqlx::migrate::MigrateError),
+    Embedded(Box<redb::Error>),
+    // A stored row's versions could not be decoded
+    Corrupt(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Postgres(e) => write!(f, "database error: {}", e),
+            StorageError::Migrate(e) => write!(f, "database migration failed: {}", e),
+            StorageError::Embedded(e) => write!(f, "embedded store error: {}", e),
+            StorageError::Corrupt(message) => write!(f, "corrupt stored versions: {}", message),
+        }
+    }
+}
+
+impl From<sqlx::Error> for StorageError {
+    fn from(e: sqlx::Error) -> Self {
+        StorageError::Postgres(e)
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for StorageError {
+    fn from(e: sqlx::migrate::MigrateError) -> Self {
+        StorageError::Migrate(e)
+    }
+}
+
+// redb has an error type per kind of operation; keep them all as one boxed redb::Error
+macro_rules! from_redb {
+    ($($error:ty),*) => {
+        $(impl From<$error> for StorageError {
+            fn from(e: $error) -> Self {
+                StorageError::Embedded(Box::new(e.into()))
+            }
+        })*
+    };
+}
+
+from_redb!(redb::Error, redb::DatabaseError, redb::TransactionError, redb::TableError, redb::StorageError, redb::CommitError);
+
+// Key-value rows and node registry, shared by every node in the process
+#[derive(Debug, Clone)]
+pub struct Stores {
+    pub keys: Arc<dyn Storage>,
+    pub registry: Arc<dyn NodeRegistry>,
+}
+
+// Open the stores the settings select; Postgres is only connected to when one of them lives there
+pub async fn open(settings: &StorageSettings) -> Result<Stores, StorageError> {
+    let pool = match settings.needs_database() {
+        true => Some(db::create_pool(settings).await?),
+        false => None,
+    };
+    // Bring the schema up to date; concurrent nodes serialize on sqlx's migration lock
+    if let Some(pool) = &pool {
+        sqlx::migrate!().run(pool).await?;
+    }
+    let keys: Arc<dyn Storage> = match (settings.backend, &pool) {
+        (StorageBackend::Postgres, Some(pool)) => Arc::new(PostgresStorage::new(pool.clone())),
+        (StorageBackend::Embedded, _) => Arc::new(EmbeddedStorage::open(&settings.path)?),
+        _ => Arc::new(MemoryStorage::new()),
+    };
+    let registry: Arc<dyn NodeRegistry> = match (settings.registry_backend(), &pool) {
+        (RegistryBackend::Postgres, Some(pool)) => Arc::new(PostgresRegistry::new(pool.clone())),
+        _ => Arc::new(MemoryRegistry::new()),
+    };
+    Ok(Stores { keys, registry })
+}
@@ -0,0 +1,221 @@
+use sqlx::{PgPool, Postgres, Transaction};
+use std::ops::Bound;
+use super::{Change, KeyCounts, KeyRange, KeyValue, Replica, Storage, StorageError, StorageFuture};
+use crate::nodes::version::{ConflictPolicy, Version};
+use crate::nodes::{Identifier, Key};
+
+// Versions go to and from the JSONB column as text
+fn encode(versions: &[Version]) -> Result<String, StorageError> {
+    serde_json::to_string(versions).map_err(|e| StorageError::Corrupt(e.to_string()))
+}
+
+fn decode(json: &str) -> Result<Vec<Version>, StorageError> {
+    serde_json::from_str(json).map_err(|e| StorageError::Corrupt(e.to_string()))
+}
+
+// Read, change and write back one row inside `tx`. The row is created first if missing and then
+// locked, so concurrent updates of the key wait their turn instead of overwriting each other.
+// With `keep_owned` a row `node` owns keeps its tag, as `Storage::replicate` says.
+async fn update_row(
+    tx: &mut Transaction<'_, Postgres>,
+    node: Identifier,
+    key: &Key,
+    replica: Replica,
+    change: Change,
+    keep_owned: bool,
+) -> Result<Vec<Version>, StorageError> {
+    let inserted = sqlx::query!(
+        "INSERT INTO key_values (key, versions, node_id, owner_id, replica_rank) VALUES ($1, '[]', $2, $3, $4)
+        ON CONFLICT (node_id, key) DO NOTHING",
+        key.as_bytes(),
+        i64::from(node),
+        i64::from(replica.owner),
+        i16::from(replica.rank)
+    )
+    .execute(&mut **tx)
+    .await?
+    .rows_affected()
+        == 1;
+    let row = sqlx::query!(
+        r#"SELECT versions::TEXT AS "versions!", owner_id, replica_rank FROM key_values
+        WHERE node_id = $1 AND key = $2 FOR UPDATE"#,
+        i64::from(node),
+        key.as_bytes()
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    let current = Replica { owner: Identifier::from(row.owner_id), rank: row.replica_rank as u8 };
+    let replica = match keep_owned && !inserted {
+        true => replica.replicated_onto(node, Some(current)),
+        false => replica,
+    };
+    let kept = change(decode(&row.versions)?);
+    if kept.is_empty() {
+        sqlx::query!(
+            "DELETE FROM key_values WHERE node_id = $1 AND key = $2",
+            i64::from(node),
+            key.as_bytes()
+        )
+        .execute(&mut **tx)
+        .await?;
+    } else {
+        sqlx::query!(
+            "UPDATE key_values SET versions = $3::TEXT::JSONB, owner_id = $4, replica_rank = $5
+            WHERE node_id = $1 AND key = $2",
+            i64::from(node),
+            key.as_bytes(),
+            encode(&kept)?,
+            i64::from(replica.owner),
+            i16::from(replica.rank)
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+    Ok(kept)
+}
+
+// Rows live in the key_values table, tagged with the owning node's id
+#[derive(Debug, Clone)]
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub fn new(pool: PgPool) -> Self {
+        PostgresStorage { pool }
+    }
+}
+
+impl Storage for PostgresStorage {
+    fn update(&self, node: Identifier, key: Key, replica: Replica, change: Change) -> StorageFuture<Vec<Version>> {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+            let kept = update_row(&mut tx, node, &key, replica, change, false).await?;
+            tx.commit().await?;
+            Ok(kept)
+        })
+    }
+
+    fn replicate(&self, node: Identifier, key: Key, replica: Replica, change: Change) -> StorageFuture<Vec<Version>> {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+            let kept = update_row(&mut tx, node, &key, replica, change, true).await?;
+            tx.commit().await?;
+            Ok(kept)
+        })
+    }
+
+    fn get(&self, node: Identifier, key: Key) -> StorageFuture<Vec<Version>> {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            let row = sqlx::query!(
+                r#"SELECT versions::TEXT AS "versions!" FROM key_values WHERE node_id = $1 AND key = $2"#,
+                i64::from(node),
+                key.as_bytes()
+            )
+            .fetch_optional(&pool)
+            .await?;
+            match row {
+                Some(row) => decode(&row.versions),
+                None => Ok(Vec::new()),
+            }
+        })
+    }
+
+    fn delete(&self, node: Identifier, key: Key) -> StorageFuture<bool> {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            let result = sqlx::query!(
+                "DELETE FROM key_values WHERE node_id = $1 AND key = $2",
+                i64::from(node),
+                key.as_bytes()
+            )
+            .execute(&pool)
+            .await?;
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    fn scan(&self, node: Identifier, range: KeyRange) -> StorageFuture<Vec<KeyValue>> {
+        let pool = self.pool.clone();
+        // One optional parameter per kind of bound; a NULL leaves that side open
+        let (from_included, from_excluded) = match range.0 {
+            Bound::Included(key) => (Some(key.into_bytes()), None),
+            Bound::Excluded(key) => (None, Some(key.into_bytes())),
+            Bound::Unbounded => (None, None),
+        };
+        let (to_included, to_excluded) = match range.1 {
+            Bound::Included(key) => (Some(key.into_bytes()), None),
+            Bound::Excluded(key) => (None, Some(key.into_bytes())),
+            Bound::Unbounded => (None, None),
+        };
+        Box::pin(async move {
+            let rows = sqlx::query!(
+                r#"SELECT key, versions::TEXT AS "versions!", owner_id, replica_rank FROM key_values WHERE node_id = $1
+                AND ($2::BYTEA IS NULL OR key >= $2)
+                AND ($3::BYTEA IS NULL OR key > $3)
+                AND ($4::BYTEA IS NULL OR key <= $4)
+                AND ($5::BYTEA IS NULL OR key < $5)
+                ORDER BY key"#,
+                i64::from(node),
+                from_included,
+                from_excluded,
+                to_included,
+                to_excluded
+            )
+            .fetch_all(&pool)
+            .await?;
+            rows.into_iter()
+                .map(|r| {
+                    Ok(KeyValue {
+                        key: Key::new(r.key),
+                        versions: decode(&r.versions)?,
+                        replica: Replica { owner: Identifier::from(r.owner_id), rank: r.replica_rank as u8 },
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn count(&self, node: Identifier) -> StorageFuture<KeyCounts> {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            let row = sqlx::query!(
+                "SELECT COUNT(*) FILTER (WHERE replica_rank = 0) AS owned, COUNT(*) FILTER (WHERE replica_rank > 0) AS replicas
+                FROM key_values WHERE node_id = $1 AND jsonb_path_exists(versions, '$[*] ? (@.value != null)')",
+                i64::from(node)
+            )
+            .fetch_one(&pool)
+            .await?;
+            Ok(KeyCounts {
+                owned: row.owned.unwrap_or(0) as usize,
+                replicas: row.replicas.unwrap_or(0) as usize,
+            })
+        })
+    }
+
+    fn transfer(&self, from: Identifier, to: Identifier, data: Vec<KeyValue>, conflicts: ConflictPolicy) -> StorageFuture<()> {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            let keys: Vec<Vec<u8>> = data.iter().map(|kv| kv.key.as_bytes().to_vec()).collect();
+            let mut tx = pool.begin().await?;
+            sqlx::query!(
+                "DELETE FROM key_values WHERE node_id = $1 AND key = ANY($2)",
+                i64::from(from),
+                &keys
+            )
+            .execute(&mut *tx)
+            .await?;
+            for kv in data {
+                let key = kv.key.clone();
+                let conflicts = conflicts.clone();
+                let merge: Change = Box::new(move |current| conflicts.merge(&key, current, kv.versions));
+                update_row(&mut tx, to, &kv.key, kv.replica, merge, false).await?;
+            }
+            tx.commit().await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use super::StorageFuture;
+use crate::nodes::Identifier;
+
+// One row of the node registry, as served by GET /nodes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeRecord {
+    pub id: i64,
+    pub address: String,
+    pub port: i64,
+    pub predecessor: Option<i64>,
+    pub successor: Option<i64>,
+    pub status: String,
+}
+
+// Which nodes exist and how they link up, kept for operators and the HTTP API; routing never reads it
+pub trait NodeRegistry: fmt::Debug + Send + Sync {
+    // Add the node, or mark it alive again if it is already known
+    fn register(&self, id: Identifier, address: String, port: i32) -> StorageFuture<()>;
+    fn list(&self) -> StorageFuture<Vec<NodeRecord>>;
+    fn set_predecessor(&self, id: Identifier, predecessor: Option<Identifier>) -> StorageFuture<()>;
+    fn set_successor(&self, id: Identifier, successor: Identifier) -> StorageFuture<()>;
+    // "alive" or "failed"
+    fn set_status(&self, id: Identifier, status: &'static str) -> StorageFuture<()>;
+    fn remove(&self, id: Identifier) -> StorageFuture<()>;
+    // Highest port any known node listens on
+    fn max_port(&self) -> StorageFuture<Option<i32>>;
+}
+
+// The nodes table
+#[derive(Debug, Clone)]
+pub struct PostgresRegistry {
+    pool: PgPool,
+}
+
+impl PostgresRegistry {
+    pub fn new(pool: PgPool) -> Self {
+        PostgresRegistry { pool }
+    }
+}
+
+impl NodeRegistry for PostgresRegistry {
+    fn register(&self, id: Identifier, address: String, port: i32) -> StorageFuture<()> {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            sqlx::query!(
+                "INSERT INTO nodes (id, address, port) VALUES ($1, $2, $3)
+                ON CONFLICT (id) DO UPDATE SET status = 'alive'",
+                i64::from(id),
+                address,
+                port
+            )
+            .execute(&pool)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn list(&self) -> StorageFuture<Vec<NodeRecord>> {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            let nodes = sqlx::query_as!(
+                NodeRecord,
+                "SELECT id, address, port, predecessor, successor, status FROM nodes ORDER BY id"
+            )
+            .fetch_all(&pool)
+            .await?;
+            Ok(nodes)
+        })
+    }
+
+    fn set_predecessor(&self, id: Identifier, predecessor: Option<Identifier>) -> StorageFuture<()> {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            sqlx::query!(
+                "UPDATE nodes SET predecessor = $1 WHERE id = $2",
+                predecessor.map(i64::from),
+                i64::from(id)
+            )
+            .execute(&pool)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn set_successor(&self, id: Identifier, successor: Identifier) -> StorageFuture<()> {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            sqlx::query!(
+                "UPDATE nodes SET successor = $1 WHERE id = $2",
+                i64::from(successor),
+                i64::from(id)
+            )
+            .execute(&pool)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn set_status(&self, id: Identifier, status: &'static str) -> StorageFuture<()> {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            sqlx::query!("UPDATE nodes SET status = $1 WHERE id = $2", status, i64::from(id))
+                .execute(&pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn remove(&self, id: Identifier) -> StorageFuture<()> {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            sqlx::query!("DELETE FROM nodes WHERE id = $1", i64::from(id))
+                .execute(&pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn max_port(&self) -> StorageFuture<Option<i32>> {
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            let record = sqlx::query!("SELECT MAX(port) AS max_port FROM nodes").fetch_one(&pool).await?;
+            Ok(record.max_port)
+        })
+    }
+}
+
+// Registry kept in process memory; it only knows the nodes of this process
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRegistry {
+    nodes: Arc<Mutex<BTreeMap<Identifier, NodeRecord>>>,
+}
+
+impl MemoryRegistry {
+    pub fn new() -> Self {
+        MemoryRegistry::default()
+    }
+
+    fn update(&self, id: Identifier, change: impl FnOnce(&mut NodeRecord)) -> StorageFuture<()> {
+        if let Some(record) = self.nodes.lock().unwrap().get_mut(&id) {
+            change(record);
+        }
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl NodeRegistry for MemoryRegistry {
+    fn register(&self, id: Identifier, address: String, port: i32) -> StorageFuture<()> {
+        self.nodes
+            .lock()
+            .unwrap()
+            .entry(id)
+            .and_modify(|record| record.status = "alive".to_string())
+            .or_insert(NodeRecord {
+                id: i64::from(id),
+                address,
+                port: i64::from(port),
+                predecessor: None,
+                successor: None,
+                status: "alive".to_string(),
+            });
+        Box::pin(async { Ok(()) })
+    }
+
+    fn list(&self) -> StorageFuture<Vec<NodeRecord>> {
+        let nodes: Vec<NodeRecord> = self.nodes.lock().unwrap().values().cloned().collect();
+        Box::pin(async move { Ok(nodes) })
+    }
+
+    fn set_predecessor(&self, id: Identifier, predecessor: Option<Identifier>) -> StorageFuture<()> {
+        self.update(id, |record| record.predecessor = predecessor.map(i64::from))
+    }
+
+    fn set_successor(&self, id: Identifier, successor: Identifier) -> StorageFuture<()> {
+        self.update(id, |record| record.successor = Some(i64::from(successor)))
+    }
+
+    fn set_status(&self, id: Identifier, status: &'static str) -> StorageFuture<()> {
+        self.update(id, |record| record.status = status.to_string())
+    }
+
+    fn remove(&self, id: Identifier) -> StorageFuture<()> {
+        self.nodes.lock().unwrap().remove(&id);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn max_port(&self) -> StorageFuture<Option<i32>> {
+        let port = self.nodes.lock().unwrap().values().map(|record| record.port as i32).max();
+        Box::pin(async move { Ok(port) })
+    }
+}
@@ -1,17 +1,58 @@
-use actix::{Actor, StreamHandler, Addr, AsyncContext, ActorContext, Handler};
-use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use actix::{Actor, StreamHandler, Addr, AsyncContext, ActorContext, Handler, Message};
+use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
-use std::time::{Duration, Instant};
-use crate::Message;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use crate::config::settings::WebSocketSettings;
+use crate::nodes::Identifier;
 
+// Connected WebSocket sessions
+pub type Clients = Arc<Mutex<HashSet<Addr<MyWebSocket>>>>;
 
 #[derive(Message, Clone, Debug)]
 #[rtype(result = "()")]
 pub struct BroadcastMessage(pub String);
 
+// Ring events pushed to every connected WebSocket client
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum NodeEvent {
+    PeerAlive { node_id: Identifier, peer_id: Identifier, role: PeerRole },
+    PeerFailed { node_id: Identifier, peer_id: Identifier, role: PeerRole },
+    NodeLeft { node_id: Identifier, successor_id: Option<Identifier>, keys_moved: usize },
+    KeysMigrated { from: Identifier, to: Identifier, keys_moved: usize },
+    // The node took over keys whose owner went away, from the replicas it held
+    ReplicasPromoted { node_id: Identifier, keys_promoted: usize },
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerRole {
+    Predecessor,
+    Successor,
+}
+
+// Send an event to every connected WebSocket client
+pub fn broadcast(clients: &Clients, event: &NodeEvent) {
+    let text = match serde_json::to_string(event) {
+        Ok(text) => text,
+        Err(e) => {
+            println!("Failed to serialize event {:?}: {:?}", event, e);
+            return;
+        }
+    };
+    for client in clients.lock().unwrap().iter() {
+        client.do_send(BroadcastMessage(text.clone()));
+    }
+}
+
 // Define WebSocket Session
 pub struct MyWebSocket {
     hb: Instant, // Last heartbeat time
+    clients: Clients,
+    heartbeat: WebSocketSettings,
 }
 
 
@@ -20,19 +61,26 @@ impl Actor for MyWebSocket {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         self.hb(ctx);
+        self.clients.lock().unwrap().insert(ctx.address());
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        self.clients.lock().unwrap().remove(&ctx.address());
     }
 }
 
 impl MyWebSocket {
-    fn new() -> Self {
+    fn new(clients: Clients, heartbeat: WebSocketSettings) -> Self {
         MyWebSocket {
             hb: Instant::now(),
+            clients,
+            heartbeat,
         }
     }
 
     fn hb(&self, ctx: &mut ws::WebsocketContext<Self>) {
-        ctx.run_interval(Duration::from_secs(5), |act, ctx| {
-            if Instant::now().duration_since(act.hb) > Duration::from_secs(10) {
+        ctx.run_interval(self.heartbeat.heartbeat_interval(), |act, ctx| {
+            if Instant::now().duration_since(act.hb) > act.heartbeat.client_timeout() {
                 println!("WebSocket client heartbeat failed, disconnecting!");
                 ctx.stop();
                 return;
@@ -70,6 +118,11 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWebSocket {
 }
 
 // WebSocket route
-pub async fn ws_route(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, actix_web::Error> {
-    ws::start(MyWebSocket::new(), &req, stream)
+pub async fn ws_route(
+    req: HttpRequest,
+    stream: web::Payload,
+    clients: web::Data<Clients>,
+    heartbeat: web::Data<WebSocketSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    ws::start(MyWebSocket::new(clients.get_ref().clone(), heartbeat.get_ref().clone()), &req, stream)
 }
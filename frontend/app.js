@@ -30,7 +30,7 @@ app.get('/', async (req, res) => {
 app.post('/add', async (req, res) => {
     const { key, value } = req.body;
     try {
-        await axios.post(`${API_BASE_URL}/add/${key}`, { value });
+        await axios.post(`${API_BASE_URL}/add/${encodeURIComponent(key)}`, { value });
         res.redirect('/');
     } catch (error) {
         console.error(error);
@@ -41,8 +41,15 @@ app.post('/add', async (req, res) => {
 app.get('/get/:key', async (req, res) => {
     const { key } = req.params;
     try {
-        const response = await axios.get(`${API_BASE_URL}/get/${key}`);
-        res.send(`Value for key ${key}: ${response.data}`);
+        // 300 means concurrent writes left several sibling values
+        const response = await axios.get(`${API_BASE_URL}/get/${encodeURIComponent(key)}`, {
+            validateStatus: (status) => status === 200 || status === 300,
+        });
+        if (response.status === 300) {
+            res.send(`Conflicting values for key ${key}: ${response.data.join(', ')}`);
+        } else {
+            res.send(`Value for key ${key}: ${response.data}`);
+        }
     } catch (error) {
         console.error(error);
         res.status(500).send('Error retrieving value');